@@ -0,0 +1,38 @@
+// Compares running a vector in place with copying the whole machine in and out
+// of every call, which is what the emulator used to do 60 times per second.
+// Run it with `cargo bench`
+use std::time::Instant;
+
+use eresma::MachineState;
+
+#[allow(clippy::redundant_clone)]
+fn main() {
+    let code = vec![
+        0x80, 0x00, 0x01, 0x06, 0x80, 0x40, 0x0b, 0x80, 0xf8, 0x0d, 0x02, 0x00,
+    ];
+    let frames = 600;
+
+    let mut state = MachineState::from_code(code.clone());
+    let start = Instant::now();
+    for _ in 0..frames {
+        state.run_vector(0x0100).unwrap();
+    }
+    let in_place = start.elapsed();
+
+    let mut state = MachineState::from_code(code);
+    let start = Instant::now();
+    for _ in 0..frames {
+        let mut copy = state.clone();
+        copy.run_vector(0x0100).unwrap();
+        state = copy.clone();
+    }
+    let copying = start.elapsed();
+
+    println!(
+        "{} vectors: in place {:?}, copying {:?} ({:.1}x faster)",
+        frames,
+        in_place,
+        copying,
+        copying.as_secs_f64() / in_place.as_secs_f64()
+    );
+}
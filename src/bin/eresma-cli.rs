@@ -0,0 +1,60 @@
+// Runs a ROM without a window, like uxncli: the reset vector, the arguments
+// and stdin go through the same CPU and devices as the windowed emulator.
+use std::env;
+use std::io::{self, Read};
+use std::process;
+
+use eresma::devices::ConsoleInput;
+use eresma::{MachineState, Options, StackError};
+
+const USAGE: &str = "Use: eresma-cli [--legacy] [--strict] ROM_FILE [ARGS...]";
+
+/// Exits on an unhandled fault, which is printed first, or when the program
+/// halts.
+fn check(machine: &MachineState, result: Result<(), StackError>) {
+    if let Err(fault) = result {
+        eprintln!("Error: {}", fault);
+    }
+    if let Some(status) = machine.exit_status(result) {
+        process::exit(status);
+    }
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) if options.info || options.zoom != 1 => {
+            eprintln!("--info and --zoom are only supported by eresma\n{}", USAGE);
+            process::exit(2);
+        }
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+    let mut machine = match options.load() {
+        Ok(machine) => machine,
+        Err(err) => {
+            eprintln!("Can't load {}: {}", options.rom, err);
+            process::exit(2);
+        }
+    };
+    let result = machine.boot(&options.args);
+    check(&machine, result);
+
+    // Only the Console vector can run from now on, so stop once it's unset
+    let mut stdin = io::stdin().lock().bytes();
+    while machine.devices.get_console_vector() != 0 {
+        match stdin.next() {
+            Some(Ok(byte)) => {
+                let result = machine.console_input(byte, ConsoleInput::Stdin);
+                check(&machine, result);
+            }
+            _ => {
+                let result = machine.console_input(0x00, ConsoleInput::ArgumentEnd);
+                check(&machine, result);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,6 @@
+use super::Device;
+
+#[derive(Clone, Default)]
+pub struct Audio;
+
+impl Device for Audio {}
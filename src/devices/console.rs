@@ -0,0 +1,69 @@
+use std::io::{self, Write};
+use std::mem;
+
+use super::{Device, DeviceContext};
+
+const WRITE: u8 = 0x8;
+const ERROR: u8 = 0x9;
+
+/// What the byte in Console/read is, stored in Console/type before the
+/// Console vector runs.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleInput {
+    Stdin = 0x1,
+    Argument = 0x2,
+    ArgumentSpacer = 0x3,
+    ArgumentEnd = 0x4,
+}
+
+/// Bytes written to Console/write and Console/error are kept as they are and
+/// buffered until `flush`, which the machine calls when a vector ends.
+#[derive(Clone, Default)]
+pub struct Console {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+impl Console {
+    pub fn pending_stdout(&self) -> &[u8] {
+        &self.stdout
+    }
+
+    pub fn pending_stderr(&self) -> &[u8] {
+        &self.stderr
+    }
+
+    /// Hands over the pending output for stdout and stderr instead of
+    /// writing it.
+    pub fn take_pending(&mut self) -> (Vec<u8>, Vec<u8>) {
+        (mem::take(&mut self.stdout), mem::take(&mut self.stderr))
+    }
+
+    /// Writes the pending output to stdout and stderr. Write errors, like a
+    /// closed pipe, are ignored.
+    pub fn flush(&mut self) {
+        if !self.stdout.is_empty() {
+            let mut stdout = io::stdout().lock();
+            let _ = stdout.write_all(&self.stdout);
+            let _ = stdout.flush();
+            self.stdout.clear();
+        }
+        if !self.stderr.is_empty() {
+            let mut stderr = io::stderr().lock();
+            let _ = stderr.write_all(&self.stderr);
+            let _ = stderr.flush();
+            self.stderr.clear();
+        }
+    }
+}
+
+impl Device for Console {
+    fn deo(&mut self, port: u8, ctx: &mut DeviceContext) {
+        match port {
+            WRITE => self.stdout.push(ctx.ports[WRITE as usize]),
+            ERROR => self.stderr.push(ctx.ports[ERROR as usize]),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,6 @@
+use super::Device;
+
+#[derive(Clone, Default)]
+pub struct File;
+
+impl Device for File {}
@@ -0,0 +1,312 @@
+mod audio;
+mod console;
+mod controller;
+mod datetime;
+mod file;
+mod mouse;
+mod screen;
+mod system;
+
+pub use audio::Audio;
+pub use console::{Console, ConsoleInput};
+pub use controller::Controller;
+pub use datetime::Datetime;
+pub use file::File;
+pub use mouse::Mouse;
+pub use screen::{Screen, SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use system::System;
+
+use crate::memory::Memory;
+use crate::stack::Stack;
+
+/// The CPU state a device can reach while handling a port.
+pub struct Cpu<'a> {
+    pub mem: &'a mut Memory,
+    pub wst: &'a Stack,
+    pub rst: &'a Stack,
+    /// Address of the instruction following the DEI/DEO.
+    pub pc: u16,
+}
+
+/// The parts of the machine a device can look at while handling a port.
+pub struct DeviceContext<'a> {
+    pub mem: &'a mut Memory,
+    pub wst: &'a Stack,
+    pub rst: &'a Stack,
+    pub pc: u16,
+    /// The 16 ports of the device in the device page.
+    pub ports: &'a mut [u8; 16],
+}
+
+/// One of the 16 device slots of a Varvara computer. Every device has 16 ports
+/// in the device page, and `port` is always relative to the device (0x0 to
+/// 0xf). Ports without side effects don't need any code: DEO always writes the
+/// value to the page and, by default, DEI reads it back.
+pub trait Device: DeviceClone {
+    fn dei(&mut self, port: u8, ctx: &mut DeviceContext) -> u8 {
+        ctx.ports[port as usize]
+    }
+
+    /// Called after the value has been written to the page.
+    fn deo(&mut self, _port: u8, _ctx: &mut DeviceContext) {}
+}
+
+/// Lets `Devices` stay `Clone` while holding boxed devices.
+pub trait DeviceClone {
+    fn clone_box(&self) -> Box<dyn Device>;
+}
+
+impl<T: Device + Clone + 'static> DeviceClone for T {
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// Slots with no device attached, they only remember what was written.
+#[derive(Clone, Default)]
+struct Unmapped;
+
+impl Device for Unmapped {}
+
+const SYSTEM_VECTOR: usize = 0x00;
+const SYSTEM_METADATA: usize = 0x06;
+const SYSTEM_RED: usize = 0x08;
+const SYSTEM_GREEN: usize = 0x0a;
+const SYSTEM_BLUE: usize = 0x0c;
+const SYSTEM_DEBUG: usize = 0x0e;
+const SYSTEM_STATE: usize = 0x0f;
+const CONSOLE_VECTOR: usize = 0x10;
+const CONSOLE_READ: usize = 0x12;
+const CONSOLE_TYPE: usize = 0x17;
+const SCREEN_VECTOR: usize = 0x20;
+const CONTROLLER_VECTOR: usize = 0x80;
+const CONTROLLER_BUTTON: usize = 0x82;
+const CONTROLLER_KEY: usize = 0x83;
+
+#[derive(Clone)]
+pub struct Devices {
+    /// The 256 bytes of device memory, 16 ports for each slot.
+    pub page: [u8; 256],
+    pub system: System,
+    pub console: Console,
+    pub screen: Screen,
+    pub audio: [Audio; 4],
+    pub controller: Controller,
+    pub mouse: Mouse,
+    pub file: [File; 2],
+    pub datetime: Datetime,
+    unmapped: [Unmapped; 4],
+    registered: [Option<Box<dyn Device>>; 16],
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        Devices {
+            page: [0; 256],
+            system: System,
+            console: Console::default(),
+            screen: Screen::default(),
+            audio: Default::default(),
+            controller: Controller,
+            mouse: Mouse,
+            file: Default::default(),
+            datetime: Datetime,
+            unmapped: Default::default(),
+            registered: Default::default(),
+        }
+    }
+}
+
+impl Devices {
+    /// Attaches `device` to `slot` (0x0 to 0xf), replacing the built-in device
+    /// of that slot, if any.
+    pub fn register(&mut self, slot: u8, device: Box<dyn Device>) {
+        self.registered[(slot & 0x0f) as usize] = Some(device);
+    }
+
+    fn device(&mut self, slot: u8) -> &mut dyn Device {
+        let slot = (slot & 0x0f) as usize;
+        if self.registered[slot].is_some() {
+            return self.registered[slot].as_deref_mut().unwrap();
+        }
+        match slot {
+            0x0 => &mut self.system,
+            0x1 => &mut self.console,
+            0x2 => &mut self.screen,
+            0x3..=0x6 => &mut self.audio[slot - 0x3],
+            0x8 => &mut self.controller,
+            0x9 => &mut self.mouse,
+            0xa | 0xb => &mut self.file[slot - 0xa],
+            0xc => &mut self.datetime,
+            0x7 => &mut self.unmapped[0],
+            _ => &mut self.unmapped[slot - 0xc],
+        }
+    }
+
+    /// Copies the ports of the slot of `port` out of the page while its device
+    /// handles them, so the device and the page can be borrowed together.
+    fn with_ports<T>(
+        &mut self,
+        port: u8,
+        cpu: &mut Cpu,
+        f: impl FnOnce(&mut dyn Device, u8, &mut DeviceContext) -> T,
+    ) -> T {
+        let base = (port & 0xf0) as usize;
+        let mut ports = [0; 16];
+        ports.copy_from_slice(&self.page[base..base + 16]);
+        let mut ctx = DeviceContext {
+            mem: cpu.mem,
+            wst: cpu.wst,
+            rst: cpu.rst,
+            pc: cpu.pc,
+            ports: &mut ports,
+        };
+        let result = f(self.device(port >> 4), port & 0x0f, &mut ctx);
+        self.page[base..base + 16].copy_from_slice(&ports);
+        result
+    }
+
+    pub fn write(&mut self, val: u8, port: u8, cpu: &mut Cpu) {
+        self.page[port as usize] = val;
+        // The stack dump goes straight to stderr, after what the program has
+        // written to the console so far
+        if port as usize == SYSTEM_DEBUG {
+            self.console.flush();
+        }
+        self.with_ports(port, cpu, |device, port, ctx| device.deo(port, ctx));
+    }
+
+    pub fn write_short(&mut self, val: u16, port: u8, cpu: &mut Cpu) {
+        self.write((val / 256) as u8, port, cpu);
+        self.write((val % 256) as u8, port.wrapping_add(1), cpu);
+    }
+
+    pub fn read(&mut self, port: u8, cpu: &mut Cpu) -> u8 {
+        self.with_ports(port, cpu, |device, port, ctx| device.dei(port, ctx))
+    }
+
+    pub fn read_short(&mut self, port: u8, cpu: &mut Cpu) -> u16 {
+        let high = self.read(port, cpu) as u16;
+        let low = self.read(port.wrapping_add(1), cpu) as u16;
+        high * 256 + low
+    }
+
+    fn peek_short(&self, port: usize) -> u16 {
+        (self.page[port] as u16) * 256 + self.page[port + 1] as u16
+    }
+
+    /// The four colors of the palette as RGBA. Each color channel is a nibble
+    /// of the System red, green and blue shorts.
+    pub fn palette(&self) -> [[u8; 4]; 4] {
+        let mut palette = [[0, 0, 0, 0xff]; 4];
+        for (i, color) in palette.iter_mut().enumerate() {
+            for (channel, port) in [SYSTEM_RED, SYSTEM_GREEN, SYSTEM_BLUE].into_iter().enumerate() {
+                let byte = self.page[port + i / 2];
+                let nibble = if i % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+                color[channel] = nibble | nibble << 4;
+            }
+        }
+        palette
+    }
+
+    /// Any non-zero System state stops the machine, and its low 7 bits are the
+    /// exit status.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.page[SYSTEM_STATE] {
+            0 => None,
+            state => Some((state & 0x7f) as i32),
+        }
+    }
+
+    /// The screen as RGBA, in the current palette.
+    pub fn screen_rgba(&self) -> Vec<u8> {
+        self.screen.to_rgba(&self.palette())
+    }
+
+    pub fn get_button(&self) -> u8 {
+        self.page[CONTROLLER_BUTTON]
+    }
+
+    pub fn set_button(&mut self, button: u8) {
+        self.page[CONTROLLER_BUTTON] = button;
+    }
+
+    pub fn set_key(&mut self, key: u8) {
+        self.page[CONTROLLER_KEY] = key;
+    }
+
+    pub fn get_system_vector(&self) -> u16 {
+        self.peek_short(SYSTEM_VECTOR)
+    }
+
+    /// Address of the ROM metadata block, zero when there is none.
+    pub fn get_metadata_address(&self) -> u16 {
+        self.peek_short(SYSTEM_METADATA)
+    }
+
+    pub fn get_console_vector(&self) -> u16 {
+        self.peek_short(CONSOLE_VECTOR)
+    }
+
+    pub fn set_console_input(&mut self, byte: u8, kind: ConsoleInput) {
+        self.page[CONSOLE_READ] = byte;
+        self.page[CONSOLE_TYPE] = kind as u8;
+    }
+
+    /// Before the reset vector runs, Console/type holds the number of
+    /// arguments the program will receive.
+    pub fn set_argument_count(&mut self, count: usize) {
+        self.page[CONSOLE_TYPE] = count.min(0xff) as u8;
+    }
+
+    pub fn get_controller_vector(&self) -> u16 {
+        self.peek_short(CONTROLLER_VECTOR)
+    }
+
+    pub fn get_screen_vector(&self) -> u16 {
+        self.peek_short(SCREEN_VECTOR)
+    }
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct CountingDevice {
+    writes: u8,
+}
+
+#[cfg(test)]
+impl Device for CountingDevice {
+    fn dei(&mut self, _port: u8, _ctx: &mut DeviceContext) -> u8 {
+        self.writes
+    }
+
+    fn deo(&mut self, _port: u8, _ctx: &mut DeviceContext) {
+        self.writes += 1;
+    }
+}
+
+#[test]
+fn registered_device() {
+    let mut mem = Memory::new();
+    let stack = Stack::new();
+    let mut cpu = Cpu {
+        mem: &mut mem,
+        wst: &stack,
+        rst: &stack,
+        pc: 0x0100,
+    };
+    let mut devices = Devices::default();
+    devices.register(0x1, Box::new(CountingDevice::default()));
+    devices.write(b'a', 0x18, &mut cpu);
+    devices.write_short(0x1234, 0x10, &mut cpu);
+    assert_eq!(devices.read(0x1f, &mut cpu), 3);
+    // Other slots keep their built-in device
+    devices.write(0x2a, 0x30, &mut cpu);
+    assert_eq!(devices.read(0x30, &mut cpu), 0x2a);
+}
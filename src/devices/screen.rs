@@ -0,0 +1,453 @@
+use super::{Device, DeviceContext};
+use crate::memory::Memory;
+
+/// Size of the screen until the program sets its own.
+pub const SCREEN_WIDTH: usize = 512;
+pub const SCREEN_HEIGHT: usize = 312;
+
+const WIDTH: usize = 0x2;
+const HEIGHT: usize = 0x4;
+const WIDTH_HIGH: u8 = 0x2;
+const WIDTH_LOW: u8 = 0x3;
+const HEIGHT_HIGH: u8 = 0x4;
+const HEIGHT_LOW: u8 = 0x5;
+const AUTO: usize = 0x6;
+const X: usize = 0x8;
+const Y: usize = 0xa;
+const ADDRESS: usize = 0xc;
+const PIXEL: u8 = 0xe;
+const SPRITE: u8 = 0xf;
+
+/// The Varvara blending table: the color drawn for each color of the sprite
+/// (rows) and each blending mode, the low nibble of the sprite byte (columns).
+const BLENDING: [[u8; 16]; 4] = [
+    [0, 0, 0, 0, 1, 0, 1, 1, 2, 2, 0, 2, 3, 3, 3, 0],
+    [0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3],
+    [1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1],
+    [2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2],
+];
+
+/// The two layers hold palette indices, one byte per pixel. Color 0 on the
+/// foreground is transparent.
+#[derive(Clone)]
+pub struct Screen {
+    width: usize,
+    height: usize,
+    pub bg: Vec<u8>,
+    pub fg: Vec<u8>,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            bg: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            fg: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+}
+
+fn port_short(ports: &[u8; 16], port: usize) -> u16 {
+    (ports[port] as u16) * 256 + ports[port + 1] as u16
+}
+
+fn set_port_short(ports: &mut [u8; 16], port: usize, val: u16) {
+    ports[port] = (val >> 8) as u8;
+    ports[port + 1] = val as u8;
+}
+
+impl Device for Screen {
+    fn dei(&mut self, port: u8, ctx: &mut DeviceContext) -> u8 {
+        match port {
+            WIDTH_HIGH => (self.width >> 8) as u8,
+            WIDTH_LOW => self.width as u8,
+            HEIGHT_HIGH => (self.height >> 8) as u8,
+            HEIGHT_LOW => self.height as u8,
+            _ => ctx.ports[port as usize],
+        }
+    }
+
+    fn deo(&mut self, port: u8, ctx: &mut DeviceContext) {
+        let val = ctx.ports[port as usize];
+        match port {
+            // The size changes once its low byte has been written
+            WIDTH_LOW => {
+                let width = port_short(ctx.ports, WIDTH) as usize;
+                self.resize(width, self.height);
+            }
+            HEIGHT_LOW => {
+                let height = port_short(ctx.ports, HEIGHT) as usize;
+                self.resize(self.width, height);
+            }
+            PIXEL => {
+                let x = port_short(ctx.ports, X);
+                let y = port_short(ctx.ports, Y);
+                let fg = val & 0x40 != 0;
+                let color = val & 0x03;
+                // Bit 7 fills from x, y to the bottom right corner, or toward
+                // the left and the top with the flip bits.
+                if val & 0x80 != 0 {
+                    let (x1, x2) = if val & 0x10 != 0 { (0, x) } else { (x, self.width as u16) };
+                    let (y1, y2) = if val & 0x20 != 0 { (0, y) } else { (y, self.height as u16) };
+                    self.fill(fg, x1, y1, x2, y2, color);
+                    return;
+                }
+                self.set_pixel(fg, x, y, color);
+                let auto = ctx.ports[AUTO];
+                if auto & 0x01 != 0 {
+                    set_port_short(ctx.ports, X, x.wrapping_add(1));
+                }
+                if auto & 0x02 != 0 {
+                    set_port_short(ctx.ports, Y, y.wrapping_add(1));
+                }
+            }
+            SPRITE => {
+                // The high nibble of auto draws that many more sprites. They
+                // go down when x moves after the DEO, and right when y does.
+                // Bit 6 picks the layer and bits 4 and 5 flip the sprite.
+                let auto = ctx.ports[AUTO];
+                let length = (auto >> 4) as u16;
+                let x = port_short(ctx.ports, X);
+                let y = port_short(ctx.ports, Y);
+                let mut address = port_short(ctx.ports, ADDRESS);
+                let dx: u16 = if auto & 0x01 != 0 { 8 } else { 0 };
+                let dy: u16 = if auto & 0x02 != 0 { 8 } else { 0 };
+                // Flipped sprites move the other way
+                let flip_x = |d: u16| if val & 0x10 != 0 { d.wrapping_neg() } else { d };
+                let flip_y = |d: u16| if val & 0x20 != 0 { d.wrapping_neg() } else { d };
+                let address_step = match (auto & 0x04 != 0, val & 0x80 != 0) {
+                    (false, _) => 0,
+                    (true, false) => 8,
+                    (true, true) => 16,
+                };
+                for i in 0..=length {
+                    let sprite_x = x.wrapping_add(flip_x(dy).wrapping_mul(i));
+                    let sprite_y = y.wrapping_add(flip_y(dx).wrapping_mul(i));
+                    self.draw_sprite(ctx.mem, address, sprite_x, sprite_y, val);
+                    address = address.wrapping_add(address_step);
+                }
+                if auto & 0x01 != 0 {
+                    set_port_short(ctx.ports, X, x.wrapping_add(flip_x(dx)));
+                }
+                if auto & 0x02 != 0 {
+                    set_port_short(ctx.ports, Y, y.wrapping_add(flip_y(dy)));
+                }
+                if auto & 0x04 != 0 {
+                    set_port_short(ctx.ports, ADDRESS, address);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Screen {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Sets a new size and clears both layers. Sizes under 8 pixels or of
+    /// 2048 pixels and more are ignored, like in the reference emulator.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        if !(8..0x800).contains(&width) || !(8..0x800).contains(&height) {
+            return;
+        }
+        self.width = width;
+        self.height = height;
+        self.bg = vec![0; width * height];
+        self.fg = vec![0; width * height];
+    }
+
+    /// Draws a 1bpp or 2bpp sprite, bit 7 of the sprite byte. A 1bpp sprite
+    /// only has colors 0 and 1. Blending modes 0x5, 0xa and 0xf leave the
+    /// pixels of color 0 untouched.
+    fn draw_sprite(&mut self, mem: &Memory, address: u16, x: u16, y: u16, val: u8) {
+        let blending = (val & 0x0f) as usize;
+        let opaque = !matches!(blending, 0x5 | 0xa | 0xf);
+        let fg = val & 0x40 != 0;
+        for i in 0..8 {
+            let low = mem[address.wrapping_add(i) as usize];
+            let high = if val & 0x80 != 0 {
+                mem[address.wrapping_add(8 + i) as usize]
+            } else {
+                0
+            };
+            for j in 0..8 {
+                let bit = 7 - j;
+                let color = (low >> bit & 1 | (high >> bit & 1) << 1) as usize;
+                if opaque || color != 0 {
+                    // Flip x and flip y mirror the sprite
+                    let px = x.wrapping_add(if val & 0x10 != 0 { 7 - j } else { j });
+                    let py = y.wrapping_add(if val & 0x20 != 0 { 7 - i } else { i });
+                    self.set_pixel(fg, px, py, BLENDING[color][blending]);
+                }
+            }
+        }
+    }
+
+    /// Fills the rectangle from `x1`, `y1` to `x2`, `y2`, both excluded.
+    fn fill(&mut self, fg: bool, x1: u16, y1: u16, x2: u16, y2: u16, color: u8) {
+        let layer = if fg { &mut self.fg } else { &mut self.bg };
+        let x2 = (x2 as usize).min(self.width);
+        let y2 = (y2 as usize).min(self.height);
+        for y in y1 as usize..y2 {
+            for x in x1 as usize..x2 {
+                layer[x + y * self.width] = color;
+            }
+        }
+    }
+
+    fn set_pixel(&mut self, fg: bool, x: u16, y: u16, color: u8) {
+        if x as usize >= self.width || y as usize >= self.height {
+            return;
+        }
+        let layer = if fg { &mut self.fg } else { &mut self.bg };
+        layer[x as usize + y as usize * self.width] = color;
+    }
+
+    /// The screen as RGBA, with the foreground over the background.
+    pub fn to_rgba(&self, palette: &[[u8; 4]; 4]) -> Vec<u8> {
+        self.bg
+            .iter()
+            .zip(&self.fg)
+            .flat_map(|(bg, fg)| palette[if *fg != 0 { *fg } else { *bg } as usize])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+use super::{Cpu, Devices};
+#[cfg(test)]
+use crate::stack::Stack;
+
+#[cfg(test)]
+fn deo(devices: &mut Devices, mem: &mut Memory, writes: &[(u8, u8)]) {
+    let stack = Stack::new();
+    let mut cpu = Cpu {
+        mem,
+        wst: &stack,
+        rst: &stack,
+        pc: 0x0100,
+    };
+    for (port, val) in writes {
+        devices.write(*val, *port, &mut cpu);
+    }
+}
+
+/// Writes `writes` to the device ports in order.
+#[cfg(test)]
+fn screen_test(mem: &mut Memory, writes: &[(u8, u8)]) -> Devices {
+    let mut devices = Devices::default();
+    deo(&mut devices, mem, writes);
+    devices
+}
+
+#[cfg(test)]
+fn color_at(layer: &[u8], x: usize, y: usize) -> u8 {
+    layer[x + y * SCREEN_WIDTH]
+}
+
+#[test]
+fn pixel_auto() {
+    let mut mem = Memory::new();
+    let devices = screen_test(
+        &mut mem,
+        &[(0x26, 0x03), (0x29, 5), (0x2b, 7), (0x2e, 0x01), (0x2e, 0x42)],
+    );
+    assert_eq!(color_at(&devices.screen.bg, 5, 7), 1);
+    assert_eq!(color_at(&devices.screen.fg, 6, 8), 2);
+    assert_eq!(devices.page[0x28..0x2c], [0, 7, 0, 9]);
+}
+
+#[test]
+fn sprite_auto_length() {
+    // Three 1bpp tiles with a single row set at row 0, 1 and 2
+    let mut mem = Memory::new();
+    mem[0x0200] = 0xff;
+    mem[0x0209] = 0xff;
+    mem[0x0212] = 0xff;
+    let devices = screen_test(
+        &mut mem,
+        &[
+            (0x26, 0x25),
+            (0x29, 16),
+            (0x2b, 8),
+            (0x2c, 0x02),
+            (0x2d, 0x00),
+            (0x2f, 0x41),
+        ],
+    );
+    // Auto x draws the extra sprites downwards
+    let fg = &devices.screen.fg;
+    assert_eq!(color_at(fg, 16, 8), 1);
+    assert_eq!(color_at(fg, 23, 17), 1);
+    assert_eq!(color_at(fg, 16, 18), 0);
+    assert_eq!(color_at(fg, 20, 26), 1);
+    assert_eq!(color_at(fg, 24, 8), 0);
+    // x moved once, the address moved past the three tiles and y stays
+    assert_eq!(devices.page[0x28..0x2e], [0, 24, 0, 8, 0x02, 0x18]);
+}
+
+#[test]
+fn sprite_auto_y_2bpp() {
+    let mut mem = Memory::new();
+    mem[0x0208] = 0x80;
+    mem[0x0218] = 0x80;
+    let devices = screen_test(
+        &mut mem,
+        &[(0x26, 0x16), (0x2c, 0x02), (0x2d, 0x00), (0x2f, 0xc1)],
+    );
+    // Auto y draws the extra sprite to the right
+    let fg = &devices.screen.fg;
+    assert_eq!(color_at(fg, 0, 0), 2);
+    assert_eq!(color_at(fg, 8, 0), 2);
+    assert_eq!(color_at(fg, 16, 0), 0);
+    assert_eq!(devices.page[0x28..0x2e], [0, 0, 0, 8, 0x02, 0x20]);
+}
+
+#[cfg(test)]
+fn layer_colors(layer: &[u8], size: usize) -> String {
+    (0..size * size)
+        .map(|i| char::from(b'0' + color_at(layer, i % size, i / size)))
+        .collect()
+}
+
+// tests/golden/sprites.txt holds the output of the reference screen.c for
+// every sprite byte, drawn at 2,2 over prefilled 12x12 layers. See
+// tests/golden/screen.c to regenerate it.
+#[test]
+fn sprite_modes_match_reference() {
+    const SIZE: usize = 12;
+    let sprite = [
+        0xf0, 0x80, 0xc0, 0xa0, 0x90, 0x88, 0x04, 0x03, 0x0f, 0x01, 0x33, 0x55, 0x00, 0xff, 0x10,
+        0x08,
+    ];
+    let golden = include_str!("../../tests/golden/sprites.txt");
+    let mut failures = Vec::new();
+    for line in golden.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let mode = u8::from_str_radix(fields[0], 16).unwrap();
+        let mut mem = Memory::new();
+        mem[0x0200..0x0210].copy_from_slice(&sprite);
+        let mut devices = screen_test(&mut mem, &[]);
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let pixel = [(0x29, x as u8), (0x2b, y as u8)];
+                deo(&mut devices, &mut mem, &pixel);
+                deo(&mut devices, &mut mem, &[(0x2e, ((x + y) % 4) as u8)]);
+                deo(&mut devices, &mut mem, &[(0x2e, 0x40 | ((x * 3 + y) % 4) as u8)]);
+            }
+        }
+        let sprite = [(0x29, 2), (0x2b, 2), (0x2c, 0x02), (0x2d, 0x00), (0x2f, mode)];
+        deo(&mut devices, &mut mem, &sprite);
+        if layer_colors(&devices.screen.bg, SIZE) != fields[1]
+            || layer_colors(&devices.screen.fg, SIZE) != fields[2]
+        {
+            failures.push(format!("{:02x}", mode));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "sprite modes differ: {}",
+        failures.join(" ")
+    );
+}
+
+#[test]
+fn transparent_blending_modes() {
+    // A 1bpp sprite with only its first column set, over a background of 3
+    let mut mem = Memory::new();
+    mem[0x0200..0x0208].copy_from_slice(&[0x80; 8]);
+    let mut devices = screen_test(&mut mem, &[]);
+    devices.screen.bg[..SCREEN_WIDTH * 8].fill(3);
+    for (x, mode) in [(0, 0x05), (8, 0x00), (16, 0x01)] {
+        deo(&mut devices, &mut mem, &[(0x29, x), (0x2c, 0x02), (0x2d, 0x00), (0x2f, mode)]);
+    }
+    let bg = &devices.screen.bg;
+    // 0x5 draws color 1 and keeps the background
+    assert_eq!((color_at(bg, 0, 0), color_at(bg, 1, 0)), (1, 3));
+    // 0x0 clears the whole tile
+    assert_eq!((color_at(bg, 8, 0), color_at(bg, 9, 0)), (0, 0));
+    // 0x1 draws color 0 as well
+    assert_eq!((color_at(bg, 16, 0), color_at(bg, 17, 0)), (1, 0));
+}
+
+#[test]
+fn foreground_color_0_is_transparent() {
+    let mut screen = Screen::default();
+    let palette = [[0, 0, 0, 0xff], [1, 1, 1, 0xff], [2, 2, 2, 0xff], [3, 3, 3, 0xff]];
+    screen.bg[0] = 1;
+    screen.bg[1] = 1;
+    screen.fg[1] = 2;
+    let rgba = screen.to_rgba(&palette);
+    assert_eq!(rgba[0..8], [1, 1, 1, 0xff, 2, 2, 2, 0xff]);
+}
+
+#[test]
+fn pixel_any_color_and_layer() {
+    let mut mem = Memory::new();
+    let devices = screen_test(&mut mem, &[(0x29, 1), (0x2e, 0x13), (0x29, 2), (0x2e, 0x62)]);
+    assert_eq!(color_at(&devices.screen.bg, 1, 0), 3);
+    assert_eq!(color_at(&devices.screen.fg, 2, 0), 2);
+}
+
+#[test]
+fn pixel_fill() {
+    let mut mem = Memory::new();
+    // Clear the background to color 2, then fill each corner of the
+    // foreground from 100,50
+    let devices = screen_test(
+        &mut mem,
+        &[(0x26, 0x03), (0x2e, 0x82), (0x29, 100), (0x2b, 50), (0x2e, 0xc1)],
+    );
+    assert!(devices.screen.bg.iter().all(|color| *color == 2));
+    let fg = &devices.screen.fg;
+    assert_eq!(color_at(fg, 100, 50), 1);
+    assert_eq!(color_at(fg, SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1), 1);
+    assert_eq!(color_at(fg, 99, 50), 0);
+    assert_eq!(color_at(fg, 100, 49), 0);
+    // Auto doesn't apply to fills
+    assert_eq!(devices.page[0x28..0x2c], [0, 100, 0, 50]);
+
+    for (mode, inside, outside) in [
+        (0xd3, (99, 50), (100, 50)),
+        (0xe3, (100, 49), (100, 50)),
+        (0xf3, (0, 0), (99, 50)),
+    ] {
+        let devices = screen_test(&mut mem, &[(0x29, 100), (0x2b, 50), (0x2e, mode)]);
+        let fg = &devices.screen.fg;
+        assert_eq!(color_at(fg, inside.0, inside.1), 3, "mode {:02x}", mode);
+        assert_eq!(color_at(fg, outside.0, outside.1), 0, "mode {:02x}", mode);
+    }
+}
+
+#[test]
+fn resize_screen() {
+    let mut mem = Memory::new();
+    let mut devices = screen_test(&mut mem, &[(0x22, 0x01), (0x23, 0x40), (0x24, 0x00), (0x25, 0xa0)]);
+    assert_eq!((devices.screen.width(), devices.screen.height()), (320, 160));
+    assert_eq!(devices.screen.bg.len(), 320 * 160);
+    assert_eq!(devices.screen_rgba().len(), 320 * 160 * 4);
+
+    // Fills and pixels are clipped to the new size
+    deo(&mut devices, &mut mem, &[(0x2e, 0x81), (0x29, 0x40), (0x2b, 0xa0), (0x2e, 0x42)]);
+    assert!(devices.screen.bg.iter().all(|color| *color == 1));
+    assert!(devices.screen.fg.iter().all(|color| *color == 0));
+
+    // Out of range sizes are ignored, and DEI reads the real size
+    deo(&mut devices, &mut mem, &[(0x24, 0x00), (0x25, 0x00)]);
+    let stack = Stack::new();
+    let mut cpu = Cpu {
+        mem: &mut mem,
+        wst: &stack,
+        rst: &stack,
+        pc: 0x0100,
+    };
+    assert_eq!(devices.read_short(0x24, &mut cpu), 160);
+    assert_eq!(devices.read_short(0x22, &mut cpu), 320);
+}
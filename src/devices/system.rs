@@ -0,0 +1,53 @@
+use super::{Device, DeviceContext};
+use crate::stack::Stack;
+
+const EXPANSION_HIGH: u8 = 0x2;
+const EXPANSION_LOW: u8 = 0x3;
+const DEBUG: u8 = 0xe;
+
+#[derive(Clone, Default)]
+pub struct System;
+
+/// Formats the contents of a stack like the reference emulator, with `<`
+/// marking the top: `WST 00 01 02 <`.
+fn stack_dump(name: &str, stack: &Stack) -> String {
+    let mut dump = name.to_string();
+    for byte in &stack.st[..stack.p as usize] {
+        dump.push_str(&format!(" {:02x}", byte));
+    }
+    dump.push_str(" <");
+    dump
+}
+
+impl Device for System {
+    fn deo(&mut self, port: u8, ctx: &mut DeviceContext) {
+        match port {
+            // Commands run once both bytes of the address have been written
+            EXPANSION_LOW => {
+                let addr = (ctx.ports[EXPANSION_HIGH as usize] as u16) << 8
+                    | ctx.ports[EXPANSION_LOW as usize] as u16;
+                ctx.mem.expansion(addr);
+            }
+            DEBUG => {
+                eprintln!("{}", stack_dump("WST", ctx.wst));
+                eprintln!("{}", stack_dump("RST", ctx.rst));
+                if ctx.ports[DEBUG as usize] != 0 {
+                    eprintln!(
+                        "PC 0x{:04x} WST.p {:02x} RST.p {:02x}",
+                        ctx.pc, ctx.wst.p, ctx.rst.p
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn stack_dump_format() {
+    let mut stack = Stack::new();
+    assert_eq!(stack_dump("RST", &stack), "RST <");
+    stack.write(0x00);
+    stack.write_short(0x0102);
+    assert_eq!(stack_dump("WST", &stack), "WST 00 01 02 <");
+}
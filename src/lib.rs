@@ -0,0 +1,17 @@
+#![allow(clippy::upper_case_acronyms)]
+//! Eresma is an UXN/Varvara computer. This crate holds the CPU, the stacks and
+//! the devices, so they can be used without the graphical frontend.
+
+pub mod devices;
+pub mod memory;
+pub mod metadata;
+mod options;
+pub mod stack;
+mod uxn;
+
+pub use devices::Devices;
+pub use memory::Memory;
+pub use metadata::Metadata;
+pub use options::Options;
+pub use stack::{Stack, StackFault};
+pub use uxn::{Instruction, MachineState, Semantics, StackError};
@@ -0,0 +1,206 @@
+use std::ops::{Deref, DerefMut};
+
+pub const BANK_SIZE: usize = 0x10000;
+pub const BANKS: usize = 16;
+
+/// Main memory, made of up to 16 banks of 64KiB. The CPU only sees bank 0,
+/// which derefs to a plain slice. The other banks are reached through the
+/// System expansion port and are allocated the first time they are written.
+///
+/// Outside of bank 0, memory is addressed with a flat offset,
+/// `bank * 0x10000 + addr`.
+#[derive(Clone)]
+pub struct Memory {
+    banks: Vec<Option<Vec<u8>>>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        let mut banks = vec![None; BANKS];
+        banks[0] = Some(vec![0; BANK_SIZE]);
+        Memory { banks }
+    }
+
+    /// Loads `rom` at 0x0100. ROMs bigger than the first bank spill over to
+    /// the next ones.
+    pub fn load(&mut self, rom: &[u8]) {
+        for (i, byte) in rom.iter().enumerate() {
+            self.set(0x0100 + i, *byte);
+        }
+    }
+
+    pub fn get(&self, offset: usize) -> u8 {
+        match self.banks.get(offset / BANK_SIZE) {
+            Some(Some(bank)) => bank[offset % BANK_SIZE],
+            _ => 0,
+        }
+    }
+
+    /// Writes beyond the last bank are ignored.
+    pub fn set(&mut self, offset: usize, val: u8) {
+        if let Some(bank) = self.banks.get_mut(offset / BANK_SIZE) {
+            bank.get_or_insert_with(|| vec![0; BANK_SIZE])[offset % BANK_SIZE] = val;
+        }
+    }
+
+    /// Runs an expansion command stored at `addr` of bank 0:
+    ///
+    /// - fill: `00 length* bank* addr* value`
+    /// - copy left: `01 length* src-bank* src-addr* dst-bank* dst-addr*`
+    /// - copy right: same as copy left, copying from the last byte backwards
+    ///
+    /// Like in the reference emulator, bank numbers wrap around the 16 banks
+    /// and addresses wrap around inside their bank. Unknown commands are
+    /// ignored.
+    pub fn expansion(&mut self, addr: u16) {
+        let arg = |i: u16| -> u16 {
+            let at = addr.wrapping_add(i) as usize;
+            (self[at] as u16) << 8 | self[addr.wrapping_add(i + 1) as usize] as u16
+        };
+        let offset = |bank: u16, addr: u16, i: usize| -> usize {
+            (bank as usize % BANKS) * BANK_SIZE + addr.wrapping_add(i as u16) as usize
+        };
+        let length = arg(1) as usize;
+        match self[addr as usize] {
+            0x00 => {
+                let (bank, dst) = (arg(3), arg(5));
+                let value = self[addr.wrapping_add(7) as usize];
+                for i in 0..length {
+                    self.set(offset(bank, dst, i), value);
+                }
+            }
+            0x01 => {
+                let (src_bank, src, dst_bank, dst) = (arg(3), arg(5), arg(7), arg(9));
+                for i in 0..length {
+                    self.set(offset(dst_bank, dst, i), self.get(offset(src_bank, src, i)));
+                }
+            }
+            0x02 => {
+                let (src_bank, src, dst_bank, dst) = (arg(3), arg(5), arg(7), arg(9));
+                for i in (0..length).rev() {
+                    self.set(offset(dst_bank, dst, i), self.get(offset(src_bank, src, i)));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.banks[0].as_deref().unwrap()
+    }
+}
+
+impl DerefMut for Memory {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.banks[0].as_deref_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+fn memory_with_command(command: &[u8]) -> Memory {
+    let mut mem = Memory::new();
+    mem[0x0200..0x0200 + command.len()].copy_from_slice(command);
+    mem
+}
+
+#[test]
+fn expansion_fill() {
+    let mut mem = memory_with_command(&[0x00, 0x00, 0x04, 0x00, 0x02, 0x00, 0x10, 0xaa]);
+    mem.expansion(0x0200);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x0f), 0x00);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x10), 0xaa);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x13), 0xaa);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x14), 0x00);
+    assert!(mem.banks[1].is_none());
+}
+
+#[test]
+fn expansion_copy_between_banks() {
+    // Copy 3 bytes from bank 0 to bank 1, then back from bank 1 to 0x0300.
+    let mut mem = memory_with_command(&[
+        0x01, 0x00, 0x03, 0x00, 0x00, 0x01, 0x00, 0x00, 0x01, 0x80, 0x00,
+    ]);
+    mem[0x0100..0x0103].copy_from_slice(&[1, 2, 3]);
+    mem.expansion(0x0200);
+    let copied: Vec<u8> = (0..3).map(|i| mem.get(BANK_SIZE + 0x8000 + i)).collect();
+    assert_eq!(copied, [1, 2, 3]);
+
+    mem[0x0210..0x021b].copy_from_slice(&[
+        0x01, 0x00, 0x03, 0x00, 0x01, 0x80, 0x00, 0x00, 0x00, 0x03, 0x00,
+    ]);
+    mem.expansion(0x0210);
+    assert_eq!(mem[0x0300..0x0303], [1, 2, 3]);
+}
+
+#[test]
+fn expansion_copy_overlapping() {
+    let mut mem = memory_with_command(&[
+        0x01, 0x00, 0x04, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x01,
+    ]);
+    mem[0x0100..0x0105].copy_from_slice(&[1, 2, 3, 4, 5]);
+    mem.expansion(0x0200);
+    assert_eq!(mem[0x0100..0x0105], [1, 1, 1, 1, 1]);
+
+    mem[0x0100..0x0105].copy_from_slice(&[1, 2, 3, 4, 5]);
+    mem[0x0200] = 0x02;
+    mem.expansion(0x0200);
+    assert_eq!(mem[0x0100..0x0105], [1, 1, 2, 3, 4]);
+}
+
+#[test]
+fn load_spills_into_next_bank() {
+    let mut mem = Memory::new();
+    mem.load(&vec![0x55; BANK_SIZE]);
+    assert_eq!(mem[0xffff], 0x55);
+    assert_eq!(mem.get(BANK_SIZE + 0xff), 0x55);
+    assert_eq!(mem.get(BANK_SIZE + 0x100), 0x00);
+}
+
+#[test]
+fn expansion_wraps_inside_the_bank() {
+    // Fill 4 bytes of bank 2 from 0xfffe
+    let mut mem = memory_with_command(&[0x00, 0x00, 0x04, 0x00, 0x02, 0xff, 0xfe, 0xaa]);
+    mem.expansion(0x0200);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0xffff), 0xaa);
+    assert_eq!(mem.get(2 * BANK_SIZE), 0xaa);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x01), 0xaa);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x02), 0x00);
+    assert!(mem.banks[3].is_none());
+
+    // Copy them back from bank 2 at 0xfffe to bank 1 at 0xffff
+    mem[0x0210..0x021b].copy_from_slice(&[
+        0x01, 0x00, 0x04, 0x00, 0x02, 0xff, 0xfe, 0x00, 0x01, 0xff, 0xff,
+    ]);
+    mem.expansion(0x0210);
+    assert_eq!(mem.get(BANK_SIZE + 0xffff), 0xaa);
+    assert_eq!(mem.get(BANK_SIZE), 0xaa);
+    assert_eq!(mem.get(BANK_SIZE + 0x02), 0xaa);
+    assert_eq!(mem.get(BANK_SIZE + 0x03), 0x00);
+    assert!(mem.banks[3..].iter().all(|bank| bank.is_none()));
+}
+
+#[test]
+fn expansion_bank_wraps_around() {
+    // Fill 2 bytes of bank 0x12, which is bank 2
+    let mut mem = memory_with_command(&[0x00, 0x00, 0x02, 0x00, 0x12, 0x00, 0x10, 0x55]);
+    mem.expansion(0x0200);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x10), 0x55);
+    assert_eq!(mem.get(2 * BANK_SIZE + 0x11), 0x55);
+
+    // Copy them from bank 0x22 to bank 0x10, which is bank 0
+    mem[0x0210..0x021b].copy_from_slice(&[
+        0x01, 0x00, 0x02, 0x00, 0x22, 0x00, 0x10, 0x00, 0x10, 0x03, 0x00,
+    ]);
+    mem.expansion(0x0210);
+    assert_eq!(mem[0x0300..0x0302], [0x55, 0x55]);
+}
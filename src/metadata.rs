@@ -0,0 +1,95 @@
+use std::fmt;
+
+/// The metadata block a ROM points to with System/metadata (0x06): a version
+/// byte, then text lines for the name, details, author and date ending with a
+/// zero byte, then a count of fields and the fields themselves, each a type
+/// byte and a short.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub version: u8,
+    pub name: String,
+    pub details: String,
+    pub author: String,
+    pub date: String,
+    pub fields: Vec<(u8, u16)>,
+}
+
+impl Metadata {
+    /// Reads the block at `addr`. Everything past the end of memory reads as
+    /// zero.
+    pub fn parse(mem: &[u8], addr: u16) -> Metadata {
+        let byte = |at: usize| mem.get(at).copied().unwrap_or(0);
+        let mut at = addr as usize;
+        let version = byte(at);
+        at += 1;
+
+        let start = at.min(mem.len());
+        let end = mem[start..]
+            .iter()
+            .position(|b| *b == 0)
+            .map_or(mem.len(), |len| start + len);
+        let text = String::from_utf8_lossy(&mem[start..end]);
+        let mut lines = text.split('\n').map(str::to_string);
+        at = end + 1;
+
+        let count = byte(at);
+        at += 1;
+        let fields = (0..count as usize)
+            .map(|i| {
+                let field = at + i * 3;
+                (byte(field), (byte(field + 1) as u16) << 8 | byte(field + 2) as u16)
+            })
+            .collect();
+
+        Metadata {
+            version,
+            name: lines.next().unwrap_or_default(),
+            details: lines.next().unwrap_or_default(),
+            author: lines.next().unwrap_or_default(),
+            date: lines.next().unwrap_or_default(),
+            fields,
+        }
+    }
+}
+
+impl fmt::Display for Metadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Name: {}", self.name)?;
+        writeln!(f, "Details: {}", self.details)?;
+        writeln!(f, "Author: {}", self.author)?;
+        writeln!(f, "Date: {}", self.date)?;
+        write!(f, "Version: {}", self.version)?;
+        for (kind, value) in &self.fields {
+            write!(f, "\nField {:02x}: {:04x}", kind, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn parse_metadata() {
+    let mut mem = vec![0; 0x200];
+    let block = b"\x00Left\nA Text Editor\nBy Hundred Rabbits\nJan 8, 2024\x00\x02\x83\x12\x34\x41\x17\x05";
+    mem[0x100..0x100 + block.len()].copy_from_slice(block);
+    let metadata = Metadata::parse(&mem, 0x100);
+    assert_eq!(
+        metadata,
+        Metadata {
+            version: 0,
+            name: "Left".to_string(),
+            details: "A Text Editor".to_string(),
+            author: "By Hundred Rabbits".to_string(),
+            date: "Jan 8, 2024".to_string(),
+            fields: vec![(0x83, 0x1234), (0x41, 0x1705)],
+        }
+    );
+}
+
+#[test]
+fn parse_metadata_name_only() {
+    let mem = b"\x00Noodle\x00".to_vec();
+    let metadata = Metadata::parse(&mem, 0);
+    assert_eq!(metadata.name, "Noodle");
+    assert_eq!(metadata.author, "");
+    assert!(metadata.fields.is_empty());
+}
@@ -0,0 +1,82 @@
+use std::io;
+
+use crate::uxn::{MachineState, Semantics};
+
+/// The command line shared by `eresma` and `eresma-cli`. Options go before
+/// the ROM, everything after it is passed to the ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub semantics: Semantics,
+    pub strict: bool,
+    /// Print the ROM metadata instead of running it.
+    pub info: bool,
+    /// Window pixels per screen pixel, from 1 to 3.
+    pub zoom: u8,
+    pub rom: String,
+    pub args: Vec<String>,
+}
+
+impl Options {
+    /// Parses the arguments following the program name. The error says what
+    /// is wrong, without the usage line.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+        let mut semantics = Semantics::Modern;
+        let mut strict = false;
+        let mut info = false;
+        let mut zoom = 1;
+        let mut args = args.into_iter();
+        let rom = loop {
+            match args.next().as_deref() {
+                Some("--legacy") => semantics = Semantics::Legacy,
+                Some("--strict") => strict = true,
+                Some("--info") => info = true,
+                Some("--zoom") => {
+                    zoom = match args.next().as_deref() {
+                        Some("1") => 1,
+                        Some("2") => 2,
+                        Some("3") => 3,
+                        _ => return Err("--zoom takes 1, 2 or 3".to_string()),
+                    }
+                }
+                Some(rom) => break rom.to_string(),
+                None => return Err("Missing ROM file".to_string()),
+            }
+        };
+        Ok(Options {
+            semantics,
+            strict,
+            info,
+            zoom,
+            rom,
+            args: args.collect(),
+        })
+    }
+
+    /// Loads the ROM into a machine set up with these options. Nothing has
+    /// run yet.
+    pub fn load(&self) -> io::Result<MachineState> {
+        let mut machine = MachineState::load_file(&self.rom)?;
+        machine.semantics = self.semantics;
+        machine.set_strict(self.strict);
+        Ok(machine)
+    }
+}
+
+#[cfg(test)]
+fn parse(args: &[&str]) -> Result<Options, String> {
+    Options::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn parse_options() {
+    let options = parse(&["--strict", "--zoom", "2", "app.rom", "--legacy", "a"]).unwrap();
+    assert_eq!(options.semantics, Semantics::Modern);
+    assert!(options.strict);
+    assert!(!options.info);
+    assert_eq!(options.zoom, 2);
+    assert_eq!(options.rom, "app.rom");
+    assert_eq!(options.args, ["--legacy", "a"]);
+
+    assert_eq!(parse(&["--legacy"]).unwrap_err(), "Missing ROM file");
+    assert!(parse(&["--zoom", "4", "app.rom"]).is_err());
+}
@@ -1,9 +1,35 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackFault {
+    Underflow,
+    Overflow,
+    DivisionByZero,
+}
+
+impl StackFault {
+    /// Error code passed to the System vector.
+    pub fn code(self) -> u8 {
+        match self {
+            StackFault::Underflow => 1,
+            StackFault::Overflow => 2,
+            StackFault::DivisionByZero => 3,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Stack {
     pub st: Vec<u8>, // Stack
-    pub p: usize,    // Pointer of the stack
-    k: usize,        // Keep Mode relative pointer
+    pub p: u8,       // Pointer of the stack, wraps around like in the reference VM
+    k: u8,           // Keep Mode relative pointer
     keep_mode: bool,
+    pub strict: bool, // Report underflows and overflows instead of wrapping
+    fault: Option<StackFault>,
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Stack::new()
+    }
 }
 
 impl Stack {
@@ -13,25 +39,46 @@ impl Stack {
             p: 0x00,
             k: 1,
             keep_mode: false,
+            strict: false,
+            fault: None,
         }
     }
 
     pub fn set_current_opcode(&mut self, opcode: u8) {
         self.k = 1; // reset keep mode relative pointer
-        if opcode < 0x80 {
-            self.keep_mode = false;
-        } else {
-            self.keep_mode = true;
+        self.keep_mode = opcode >= 0x80;
+    }
+
+    /// Returns the fault raised since the last call, if any. Faults are only
+    /// raised in strict mode.
+    pub fn take_fault(&mut self) -> Option<StackFault> {
+        self.fault.take()
+    }
+
+    /// Whether a fault has been raised and not taken yet. A faulting
+    /// instruction must not touch memory or devices.
+    pub fn faulted(&self) -> bool {
+        self.fault.is_some()
+    }
+
+    /// Raises `fault` in strict mode.
+    pub fn raise(&mut self, fault: StackFault) {
+        if self.strict {
+            self.fault.get_or_insert(fault);
         }
     }
 
     pub fn read(&mut self) -> u8 {
-        let a = self.st[self.p - self.k];
+        if self.strict && self.k > self.p {
+            self.raise(StackFault::Underflow);
+            return 0;
+        }
+        let a = self.st[self.p.wrapping_sub(self.k) as usize];
         // check keep mode bit, on keep mode, global pointer doesn't change but keep mode relative pointer does
         if !self.keep_mode {
-            self.p -= 1;
+            self.p = self.p.wrapping_sub(1);
         } else {
-            self.k += 1;
+            self.k = self.k.wrapping_add(1);
         }
         a
     }
@@ -43,8 +90,12 @@ impl Stack {
     }
 
     pub fn write(&mut self, data: u8) {
-        self.st[self.p] = data;
-        self.p += 1;
+        if self.strict && self.p == 0xff {
+            self.raise(StackFault::Overflow);
+            return;
+        }
+        self.st[self.p as usize] = data;
+        self.p = self.p.wrapping_add(1);
     }
 
     pub fn write_short(&mut self, data: u16) {
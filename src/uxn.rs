@@ -0,0 +1,2233 @@
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+
+use num_enum::FromPrimitive;
+
+use crate::devices::{ConsoleInput, Cpu, Devices};
+use crate::memory::Memory;
+use crate::metadata::Metadata;
+use crate::stack::{Stack, StackFault};
+
+// https://wiki.xxiivv.com/site/uxntal_reference.html
+// Every one of the 256 opcodes has a variant, so the default that FromPrimitive
+// requires is never actually used to fill a gap.
+#[repr(u8)]
+#[derive(FromPrimitive, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    #[num_enum(default)]
+    BRK = 0x00,
+    INC = 0x01,
+    POP = 0x02,
+    NIP = 0x03,
+    SWP = 0x04,
+    ROT = 0x05,
+    DUP = 0x06,
+    OVR = 0x07,
+    EQU = 0x08,
+    NEQ = 0x09,
+    GTH = 0x0a,
+    LTH = 0x0b,
+    JMP = 0x0c,
+    JCN = 0x0d,
+    JSR = 0x0e,
+    STH = 0x0f,
+    LDZ = 0x10,
+    STZ = 0x11,
+    LDR = 0x12,
+    STR = 0x13,
+    LDA = 0x14,
+    STA = 0x15,
+    DEI = 0x16,
+    DEO = 0x17,
+    ADD = 0x18,
+    SUB = 0x19,
+    MUL = 0x1a,
+    DIV = 0x1b,
+    AND = 0x1c,
+    ORA = 0x1d,
+    EOR = 0x1e,
+    SFT = 0x1f,
+    JCI = 0x20,
+    INC2 = 0x21,
+    POP2 = 0x22,
+    NIP2 = 0x23,
+    SWP2 = 0x24,
+    ROT2 = 0x25,
+    DUP2 = 0x26,
+    OVR2 = 0x27,
+    EQU2 = 0x28,
+    NEQ2 = 0x29,
+    GTH2 = 0x2a,
+    LTH2 = 0x2b,
+    JMP2 = 0x2c,
+    JCN2 = 0x2d,
+    JSR2 = 0x2e,
+    STH2 = 0x2f,
+    LDZ2 = 0x30,
+    STZ2 = 0x31,
+    LDR2 = 0x32,
+    STR2 = 0x33,
+    LDA2 = 0x34,
+    STA2 = 0x35,
+    DEI2 = 0x36,
+    DEO2 = 0x37,
+    ADD2 = 0x38,
+    SUB2 = 0x39,
+    MUL2 = 0x3a,
+    DIV2 = 0x3b,
+    AND2 = 0x3c,
+    ORA2 = 0x3d,
+    EOR2 = 0x3e,
+    SFT2 = 0x3f,
+    JMI = 0x40,
+    INCr = 0x41,
+    POPr = 0x42,
+    NIPr = 0x43,
+    SWPr = 0x44,
+    ROTr = 0x45,
+    DUPr = 0x46,
+    OVRr = 0x47,
+    EQUr = 0x48,
+    NEQr = 0x49,
+    GTHr = 0x4a,
+    LTHr = 0x4b,
+    JMPr = 0x4c,
+    JCNr = 0x4d,
+    JSRr = 0x4e,
+    STHr = 0x4f,
+    LDZr = 0x50,
+    STZr = 0x51,
+    LDRr = 0x52,
+    STRr = 0x53,
+    LDAr = 0x54,
+    STAr = 0x55,
+    DEIr = 0x56,
+    DEOr = 0x57,
+    ADDr = 0x58,
+    SUBr = 0x59,
+    MULr = 0x5a,
+    DIVr = 0x5b,
+    ANDr = 0x5c,
+    ORAr = 0x5d,
+    EORr = 0x5e,
+    SFTr = 0x5f,
+    JSI = 0x60,
+    INC2r = 0x61,
+    POP2r = 0x62,
+    NIP2r = 0x63,
+    SWP2r = 0x64,
+    ROT2r = 0x65,
+    DUP2r = 0x66,
+    OVR2r = 0x67,
+    EQU2r = 0x68,
+    NEQ2r = 0x69,
+    GTH2r = 0x6a,
+    LTH2r = 0x6b,
+    JMP2r = 0x6c,
+    JCN2r = 0x6d,
+    JSR2r = 0x6e,
+    STH2r = 0x6f,
+    LDZ2r = 0x70,
+    STZ2r = 0x71,
+    LDR2r = 0x72,
+    STR2r = 0x73,
+    LDA2r = 0x74,
+    STA2r = 0x75,
+    DEI2r = 0x76,
+    DEO2r = 0x77,
+    ADD2r = 0x78,
+    SUB2r = 0x79,
+    MUL2r = 0x7a,
+    DIV2r = 0x7b,
+    AND2r = 0x7c,
+    ORA2r = 0x7d,
+    EOR2r = 0x7e,
+    SFT2r = 0x7f,
+    LIT = 0x80,
+    INCk = 0x81,
+    POPk = 0x82,
+    NIPk = 0x83,
+    SWPk = 0x84,
+    ROTk = 0x85,
+    DUPk = 0x86,
+    OVRk = 0x87,
+    EQUk = 0x88,
+    NEQk = 0x89,
+    GTHk = 0x8a,
+    LTHk = 0x8b,
+    JMPk = 0x8c,
+    JCNk = 0x8d,
+    JSRk = 0x8e,
+    STHk = 0x8f,
+    LDZk = 0x90,
+    STZk = 0x91,
+    LDRk = 0x92,
+    STRk = 0x93,
+    LDAk = 0x94,
+    STAk = 0x95,
+    DEIk = 0x96,
+    DEOk = 0x97,
+    ADDk = 0x98,
+    SUBk = 0x99,
+    MULk = 0x9a,
+    DIVk = 0x9b,
+    ANDk = 0x9c,
+    ORAk = 0x9d,
+    EORk = 0x9e,
+    SFTk = 0x9f,
+    LIT2 = 0xa0,
+    INC2k = 0xa1,
+    POP2k = 0xa2,
+    NIP2k = 0xa3,
+    SWP2k = 0xa4,
+    ROT2k = 0xa5,
+    DUP2k = 0xa6,
+    OVR2k = 0xa7,
+    EQU2k = 0xa8,
+    NEQ2k = 0xa9,
+    GTH2k = 0xaa,
+    LTH2k = 0xab,
+    JMP2k = 0xac,
+    JCN2k = 0xad,
+    JSR2k = 0xae,
+    STH2k = 0xaf,
+    LDZ2k = 0xb0,
+    STZ2k = 0xb1,
+    LDR2k = 0xb2,
+    STR2k = 0xb3,
+    LDA2k = 0xb4,
+    STA2k = 0xb5,
+    DEI2k = 0xb6,
+    DEO2k = 0xb7,
+    ADD2k = 0xb8,
+    SUB2k = 0xb9,
+    MUL2k = 0xba,
+    DIV2k = 0xbb,
+    AND2k = 0xbc,
+    ORA2k = 0xbd,
+    EOR2k = 0xbe,
+    SFT2k = 0xbf,
+    LITr = 0xc0,
+    INCkr = 0xc1,
+    POPkr = 0xc2,
+    NIPkr = 0xc3,
+    SWPkr = 0xc4,
+    ROTkr = 0xc5,
+    DUPkr = 0xc6,
+    OVRkr = 0xc7,
+    EQUkr = 0xc8,
+    NEQkr = 0xc9,
+    GTHkr = 0xca,
+    LTHkr = 0xcb,
+    JMPkr = 0xcc,
+    JCNkr = 0xcd,
+    JSRkr = 0xce,
+    STHkr = 0xcf,
+    LDZkr = 0xd0,
+    STZkr = 0xd1,
+    LDRkr = 0xd2,
+    STRkr = 0xd3,
+    LDAkr = 0xd4,
+    STAkr = 0xd5,
+    DEIkr = 0xd6,
+    DEOkr = 0xd7,
+    ADDkr = 0xd8,
+    SUBkr = 0xd9,
+    MULkr = 0xda,
+    DIVkr = 0xdb,
+    ANDkr = 0xdc,
+    ORAkr = 0xdd,
+    EORkr = 0xde,
+    SFTkr = 0xdf,
+    LIT2r = 0xe0,
+    INC2kr = 0xe1,
+    POP2kr = 0xe2,
+    NIP2kr = 0xe3,
+    SWP2kr = 0xe4,
+    ROT2kr = 0xe5,
+    DUP2kr = 0xe6,
+    OVR2kr = 0xe7,
+    EQU2kr = 0xe8,
+    NEQ2kr = 0xe9,
+    GTH2kr = 0xea,
+    LTH2kr = 0xeb,
+    JMP2kr = 0xec,
+    JCN2kr = 0xed,
+    JSR2kr = 0xee,
+    STH2kr = 0xef,
+    LDZ2kr = 0xf0,
+    STZ2kr = 0xf1,
+    LDR2kr = 0xf2,
+    STR2kr = 0xf3,
+    LDA2kr = 0xf4,
+    STA2kr = 0xf5,
+    DEI2kr = 0xf6,
+    DEO2kr = 0xf7,
+    ADD2kr = 0xf8,
+    SUB2kr = 0xf9,
+    MUL2kr = 0xfa,
+    DIV2kr = 0xfb,
+    AND2kr = 0xfc,
+    ORA2kr = 0xfd,
+    EOR2kr = 0xfe,
+    SFT2kr = 0xff,
+}
+
+impl Instruction {
+    pub fn is_return(self) -> bool {
+        // JMI and JSI always use the real return stack, they have no modes
+        self as u8 & 0x40 != 0 && !matches!(self, Instruction::JMI | Instruction::JSI)
+    }
+}
+
+/// Meaning of the 0x20, 0x40 and 0x60 opcodes. Older Uxn ignored the keep bit
+/// of LIT, so they behaved as LIT2, LITr and LIT2r. Current Uxn uses them for
+/// the JCI, JMI and JSI immediate jumps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Semantics {
+    Legacy,
+    Modern,
+}
+
+/// A stack underflow, overflow or division by zero caught while running in
+/// strict mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackError {
+    pub fault: StackFault,
+    pub return_stack: bool,
+    pub opcode: u8,
+    pub pc: u16,
+}
+
+impl fmt::Display for StackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stack = if self.return_stack { "Return-stack" } else { "Working-stack" };
+        let fault = match self.fault {
+            StackFault::Underflow => "underflow",
+            StackFault::Overflow => "overflow",
+            StackFault::DivisionByZero => "division by zero",
+        };
+        write!(
+            f,
+            "{} {} at 0x{:04x} ({:?})",
+            stack,
+            fault,
+            self.pc,
+            Instruction::from(self.opcode)
+        )
+    }
+}
+
+#[derive(Clone)]
+pub struct MachineState {
+    pub wst: Stack,
+    pub rst: Stack,
+    pub mem: Memory,
+    pub pc: u16,
+    pub devices: Devices,
+    pub semantics: Semantics,
+}
+
+impl MachineState {
+    pub fn from_code(code: Vec<u8>) -> Self {
+        let mut mem = Memory::new();
+        mem.load(&code);
+        MachineState {
+            wst: Stack::new(),
+            rst: Stack::new(),
+            mem,
+            pc: 0x0100,
+            devices: Devices::default(),
+            semantics: Semantics::Modern,
+        }
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.wst.strict = strict;
+        self.rst.strict = strict;
+    }
+
+    /// A program halts by writing a non-zero value to the System state port.
+    pub fn halted(&self) -> bool {
+        self.devices.exit_code().is_some()
+    }
+
+    /// The exit status requested by the program once it has halted.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.devices.exit_code()
+    }
+
+    /// What a runner should exit with after `result`: 1 after an unhandled
+    /// fault, or the status the program asked for when it halted. `None`
+    /// means the program keeps running.
+    pub fn exit_status(&self, result: Result<(), StackError>) -> Option<i32> {
+        match result {
+            Err(_) => Some(1),
+            Ok(()) => self.exit_code(),
+        }
+    }
+
+    /// Delivers one byte of console input, running the Console vector if the
+    /// program has set one.
+    pub fn console_input(&mut self, byte: u8, kind: ConsoleInput) -> Result<(), StackError> {
+        self.devices.set_console_input(byte, kind);
+        match self.devices.get_console_vector() {
+            0 => Ok(()),
+            vector => self.run_vector(vector),
+        }
+    }
+
+    /// Runs the reset vector and then passes `args` to the Console vector byte
+    /// by byte, like uxncli: each argument ends with a newline typed as a
+    /// spacer, or as the end of the arguments for the last one.
+    pub fn boot(&mut self, args: &[String]) -> Result<(), StackError> {
+        self.devices.set_argument_count(args.len());
+        self.run_vector(0x0100)?;
+        for (i, arg) in args.iter().enumerate() {
+            for byte in arg.bytes() {
+                self.console_input(byte, ConsoleInput::Argument)?;
+            }
+            let kind = if i == args.len() - 1 {
+                ConsoleInput::ArgumentEnd
+            } else {
+                ConsoleInput::ArgumentSpacer
+            };
+            self.console_input(b'\n', kind)?;
+        }
+        Ok(())
+    }
+
+    /// The metadata the program points to in System/metadata, usually from
+    /// its reset vector.
+    pub fn metadata(&self) -> Option<Metadata> {
+        match self.devices.get_metadata_address() {
+            0 => None,
+            addr => Some(Metadata::parse(&self.mem, addr)),
+        }
+    }
+
+    pub fn load_file(file: &str) -> Result<MachineState, std::io::Error> {
+        let mut file = File::open(file)?;
+        let mut buffer = Vec::new();
+        file.read_to_end(&mut buffer)?;
+        let mut mem = Memory::new();
+        mem.load(&buffer);
+        Ok(MachineState {
+            wst: Stack::new(),
+            rst: Stack::new(),
+            mem,
+            pc: 0x0100,
+            devices: Devices::default(),
+            semantics: Semantics::Modern,
+        })
+    }
+}
+
+/// Reads the short at `addr`, wrapping around the end of memory.
+fn peek_short(mem: &[u8], addr: u16) -> u16 {
+    (mem[addr as usize] as u16) << 8 | mem[addr.wrapping_add(1) as usize] as u16
+}
+
+/// Writes the short at `addr`, wrapping around the end of memory.
+fn poke_short(mem: &mut [u8], addr: u16, val: u16) {
+    mem[addr as usize] = (val >> 8) as u8;
+    mem[addr.wrapping_add(1) as usize] = val as u8;
+}
+
+/// What devices see of the CPU, with the stacks swapped back in return mode.
+fn cpu<'a>(mem: &'a mut Memory, wst: &'a Stack, rst: &'a Stack, return_mode: bool, pc: u16) -> Cpu<'a> {
+    let (wst, rst) = if return_mode { (rst, wst) } else { (wst, rst) };
+    Cpu { mem, wst, rst, pc }
+}
+
+impl MachineState {
+    /// Runs the instruction at `pc`. Returns `Ok(false)` when it is a BRK,
+    /// leaving `pc` pointing at it, or when the machine has been halted.
+    pub fn step(&mut self) -> Result<bool, StackError> {
+        if self.halted() {
+            return Ok(false);
+        }
+        let semantics = self.semantics;
+        let opcode_pc = self.pc;
+        let mut pc = self.pc;
+        let MachineState {
+            wst: real_wst,
+            rst: real_rst,
+            mem,
+            devices,
+            ..
+        } = self;
+        let opcode = mem[pc as usize];
+        pc = pc.wrapping_add(1);
+        let instruction = match (semantics, Instruction::from(opcode)) {
+            (Semantics::Legacy, Instruction::JCI) => Instruction::LIT2,
+            (Semantics::Legacy, Instruction::JMI) => Instruction::LITr,
+            (Semantics::Legacy, Instruction::JSI) => Instruction::LIT2r,
+            (_, instruction) => instruction,
+        };
+        let return_mode = instruction.is_return();
+        let (wst, rst) = if return_mode {
+            (&mut *real_rst, &mut *real_wst)
+        } else {
+            (&mut *real_wst, &mut *real_rst)
+        };
+
+        wst.set_current_opcode(opcode);
+        match instruction {
+            Instruction::BRK => return Ok(false),
+            Instruction::JCI => {
+                let cond = wst.read();
+                let offset = peek_short(mem, pc);
+                pc = pc.wrapping_add(2);
+                if cond != 0 {
+                    pc = pc.wrapping_add(offset);
+                }
+            }
+            Instruction::JMI => {
+                let offset = peek_short(mem, pc);
+                pc = pc.wrapping_add(2).wrapping_add(offset);
+            }
+            Instruction::JSI => {
+                let offset = peek_short(mem, pc);
+                pc = pc.wrapping_add(2);
+                rst.write_short(pc);
+                pc = pc.wrapping_add(offset);
+            }
+            Instruction::LIT | Instruction::LITr => {
+                wst.write(mem[pc as usize]);
+                pc = pc.wrapping_add(1);
+            }
+            Instruction::LIT2 | Instruction::LIT2r => {
+                wst.write_short(peek_short(mem, pc));
+                pc = pc.wrapping_add(2);
+            }
+            Instruction::INC | Instruction::INCk | Instruction::INCr | Instruction::INCkr => {
+                let a = wst.read();
+                wst.write(a.wrapping_add(1));
+            }
+	    Instruction::INC2 | Instruction::INC2k | Instruction::INC2r | Instruction::INC2kr => {
+		let a = wst.read_short();
+		wst.write_short(a.wrapping_add(1));
+	    }
+            Instruction::POP | Instruction::POPk | Instruction::POPr | Instruction::POPkr => {
+                wst.read();
+            }
+            Instruction::POP2 | Instruction::POP2k | Instruction::POP2r | Instruction::POP2kr => {
+                wst.read_short();
+            }	    
+            Instruction::NIP | Instruction::NIPk | Instruction::NIPr | Instruction::NIPkr => {
+                let b = wst.read();
+                let _ = wst.read();
+                wst.write(b);
+            }
+            Instruction::NIP2 | Instruction::NIP2k | Instruction::NIP2r | Instruction::NIP2kr => {
+                let b = wst.read_short();
+                let _ = wst.read_short();
+                wst.write_short(b);
+            }	    
+            Instruction::SWP | Instruction::SWPk | Instruction::SWPr | Instruction::SWPkr => {
+                let b = wst.read();
+                let a = wst.read();
+                wst.write(b);
+                wst.write(a);
+            }
+            Instruction::SWP2 | Instruction::SWP2k | Instruction::SWP2r | Instruction::SWP2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                wst.write_short(b);
+                wst.write_short(a);
+            }	    
+            Instruction::ROT | Instruction::ROTk | Instruction::ROTr | Instruction::ROTkr => {
+                let c = wst.read();
+                let b = wst.read();
+                let a = wst.read();
+                wst.write(b);
+                wst.write(c);
+                wst.write(a);
+            }
+            Instruction::ROT2 | Instruction::ROT2k | Instruction::ROT2r | Instruction::ROT2kr => {
+                let c = wst.read_short();
+                let b = wst.read_short();
+                let a = wst.read_short();
+                wst.write_short(b);
+                wst.write_short(c);
+                wst.write_short(a);
+            }	    
+            Instruction::DUP | Instruction::DUPk | Instruction::DUPr | Instruction::DUPkr => {
+                let a = wst.read();
+                wst.write(a);
+                wst.write(a);
+            }
+            Instruction::DUP2 | Instruction::DUP2k | Instruction::DUP2r | Instruction::DUP2kr => {
+                let a = wst.read_short();
+                wst.write_short(a);
+                wst.write_short(a);
+            }	    
+            Instruction::OVR | Instruction::OVRk | Instruction::OVRr | Instruction::OVRkr => {
+                let b = wst.read();
+                let a = wst.read();
+                wst.write(a);
+                wst.write(b);
+                wst.write(a);
+            }
+            Instruction::OVR2 | Instruction::OVR2k | Instruction::OVR2r | Instruction::OVR2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                wst.write_short(a);
+                wst.write_short(b);
+                wst.write_short(a);
+            }	    
+            Instruction::EQU | Instruction::EQUk | Instruction::EQUr | Instruction::EQUkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = if a == b { 0x01 } else { 0x00 };
+                wst.write(c);
+            }
+            Instruction::EQU2 | Instruction::EQU2k | Instruction::EQU2r | Instruction::EQU2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = if a == b { 0x01 } else { 0x00 };
+                wst.write(c);
+            }	    
+            Instruction::NEQ | Instruction::NEQk | Instruction::NEQr | Instruction::NEQkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = if a == b { 0x00 } else { 0x01 };
+                wst.write(c);
+            }
+            Instruction::NEQ2 | Instruction::NEQ2k | Instruction::NEQ2r | Instruction::NEQ2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = if a == b { 0x00 } else { 0x01 };
+                wst.write(c);
+            }	    
+            Instruction::GTH | Instruction::GTHk | Instruction::GTHr | Instruction::GTHkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = if a > b { 0x01 } else { 0x00 };
+                wst.write(c);
+            }
+            Instruction::GTH2 | Instruction::GTH2k | Instruction::GTH2r | Instruction::GTH2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = if a > b { 0x01 } else { 0x00 };
+                wst.write(c);
+            }	    
+            Instruction::LTH | Instruction::LTHk | Instruction::LTHr | Instruction::LTHkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = if a < b { 0x01 } else { 0x00 };
+                wst.write(c);
+            }
+            Instruction::LTH2 | Instruction::LTH2k | Instruction::LTH2r | Instruction::LTH2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = if a < b { 0x01 } else { 0x00 };
+                wst.write(c);
+            }	    
+            Instruction::JMP | Instruction::JMPk | Instruction::JMPr | Instruction::JMPkr => {
+                let offset = wst.read() as i8;
+                pc = pc.wrapping_add_signed(offset as i16);
+            }
+            Instruction::JMP2 | Instruction::JMP2k | Instruction::JMP2r | Instruction::JMP2kr => {
+                pc = wst.read_short();
+            }
+            Instruction::JCN | Instruction::JCNk | Instruction::JCNr | Instruction::JCNkr => {
+                let offset = wst.read() as i8;
+                let cond = wst.read();
+                if cond != 0 {
+                    pc = pc.wrapping_add_signed(offset as i16);
+                }
+            }
+            Instruction::JCN2 | Instruction::JCN2k | Instruction::JCN2r | Instruction::JCN2kr => {
+                let addr = wst.read_short();
+                let cond = wst.read();
+                if cond != 0 {
+                    pc = addr;
+                }
+            }
+            Instruction::JSR | Instruction::JSRk | Instruction::JSRr | Instruction::JSRkr => {
+                let offset = wst.read() as i8;
+                rst.write_short(pc);
+                pc = pc.wrapping_add_signed(offset as i16);
+            }
+            Instruction::JSR2 | Instruction::JSR2k | Instruction::JSR2r | Instruction::JSR2kr => {
+                let addr = wst.read_short();
+                rst.write_short(pc);
+                pc = addr;
+            }
+            Instruction::STH | Instruction::STHk | Instruction::STHr | Instruction::STHkr => {
+                let a = wst.read();
+                rst.write(a);
+            }
+            Instruction::STH2 | Instruction::STH2k | Instruction::STH2r | Instruction::STH2kr => {
+                let a = wst.read_short();
+                rst.write_short(a);
+            }
+            Instruction::LDZ | Instruction::LDZk | Instruction::LDZr | Instruction::LDZkr => {
+                let addr = wst.read();
+                let val = mem[addr as usize];
+                wst.write(val);
+            }
+            Instruction::LDZ2 | Instruction::LDZ2k | Instruction::LDZ2r | Instruction::LDZ2kr => {
+                let addr = wst.read() as u16;
+                wst.write_short(peek_short(mem, addr));
+            }
+            Instruction::STZ | Instruction::STZk | Instruction::STZr | Instruction::STZkr => {
+                let addr = wst.read();
+                let val = wst.read();
+                if !wst.faulted() {
+                    mem[addr as usize] = val;
+                }
+            }
+            Instruction::STZ2 | Instruction::STZ2k | Instruction::STZ2r | Instruction::STZ2kr => {
+                let addr = wst.read() as u16;
+                let val = wst.read_short();
+                if !wst.faulted() {
+                    poke_short(mem, addr, val);
+                }
+            }
+            Instruction::LDR | Instruction::LDRk | Instruction::LDRr | Instruction::LDRkr => {
+                let offset = wst.read() as i8;
+                let addr = pc.wrapping_add_signed(offset as i16);
+                wst.write(mem[addr as usize]);
+            }
+            Instruction::LDR2 | Instruction::LDR2k | Instruction::LDR2r | Instruction::LDR2kr => {
+                let offset = wst.read() as i8;
+                let addr = pc.wrapping_add_signed(offset as i16);
+                wst.write_short(peek_short(mem, addr));
+            }
+            Instruction::STR | Instruction::STRk | Instruction::STRr | Instruction::STRkr => {
+                let offset = wst.read() as i8;
+                let val = wst.read();
+                let addr = pc.wrapping_add_signed(offset as i16);
+                if !wst.faulted() {
+                    mem[addr as usize] = val;
+                }
+            }
+            Instruction::STR2 | Instruction::STR2k | Instruction::STR2r | Instruction::STR2kr => {
+                let offset = wst.read() as i8;
+                let val = wst.read_short();
+                let addr = pc.wrapping_add_signed(offset as i16);
+                if !wst.faulted() {
+                    poke_short(mem, addr, val);
+                }
+            }
+            Instruction::LDA | Instruction::LDAk | Instruction::LDAr | Instruction::LDAkr => {
+                let addr = wst.read_short();
+                wst.write(mem[addr as usize]);
+            }
+            Instruction::LDA2 | Instruction::LDA2k | Instruction::LDA2r | Instruction::LDA2kr => {
+                let addr = wst.read_short();
+                wst.write_short(peek_short(mem, addr));
+            }
+            Instruction::STA | Instruction::STAk | Instruction::STAr | Instruction::STAkr => {
+                let addr = wst.read_short();
+                let val = wst.read();
+                if !wst.faulted() {
+                    mem[addr as usize] = val;
+                }
+            }
+            Instruction::STA2 | Instruction::STA2k | Instruction::STA2r | Instruction::STA2kr => {
+                let addr = wst.read_short();
+                let val = wst.read_short();
+                if !wst.faulted() {
+                    poke_short(mem, addr, val);
+                }
+            }
+            Instruction::DEI | Instruction::DEIk | Instruction::DEIr | Instruction::DEIkr => {
+                let device = wst.read();
+                if !wst.faulted() {
+                    let val = devices.read(device, &mut cpu(mem, wst, rst, return_mode, pc));
+                    wst.write(val);
+                }
+            }
+            Instruction::DEO | Instruction::DEOk | Instruction::DEOr | Instruction::DEOkr => {
+                let device = wst.read();
+                let val = wst.read();
+                if !wst.faulted() {
+                    devices.write(val, device, &mut cpu(mem, wst, rst, return_mode, pc));
+                }
+            }
+            Instruction::ADD | Instruction::ADDk | Instruction::ADDr | Instruction::ADDkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = a.wrapping_add(b);
+                wst.write(c);
+            }
+            Instruction::ADD2 | Instruction::ADD2k | Instruction::ADD2r | Instruction::ADD2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = a.wrapping_add(b);
+                wst.write_short(c);
+            }	    
+            Instruction::SUB | Instruction::SUBk | Instruction::SUBr | Instruction::SUBkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = a.wrapping_sub(b);
+                wst.write(c);
+            }
+            Instruction::SUB2 | Instruction::SUB2k | Instruction::SUB2r | Instruction::SUB2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = a.wrapping_sub(b);
+                wst.write_short(c);
+            }	    
+            Instruction::MUL | Instruction::MULk | Instruction::MULr | Instruction::MULkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = a.wrapping_mul(b);
+                wst.write(c);
+            }
+            Instruction::MUL2 | Instruction::MUL2k | Instruction::MUL2r | Instruction::MUL2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = a.wrapping_mul(b);
+                wst.write_short(c);
+            }
+	    Instruction::DIV | Instruction::DIVk | Instruction::DIVr | Instruction::DIVkr => {
+		let b = wst.read();
+		let a = wst.read();
+		if b == 0 {
+		    wst.raise(StackFault::DivisionByZero);
+		}
+		let c = a.checked_div(b).unwrap_or(0);
+		wst.write(c);
+	    }
+	    Instruction::DIV2 | Instruction::DIV2k | Instruction::DIV2r | Instruction::DIV2kr => {
+		let b = wst.read_short();
+		let a = wst.read_short();
+		if b == 0 {
+		    wst.raise(StackFault::DivisionByZero);
+		}
+		let c = a.checked_div(b).unwrap_or(0);
+		wst.write_short(c);
+	    }
+            Instruction::AND | Instruction::ANDk | Instruction::ANDr | Instruction::ANDkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = a & b;
+                wst.write(c);
+            }
+            Instruction::AND2 | Instruction::AND2k | Instruction::AND2r | Instruction::AND2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = a & b;
+                wst.write_short(c);
+            }	    
+            Instruction::ORA | Instruction::ORAk | Instruction::ORAr | Instruction::ORAkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = a | b;
+                wst.write(c);
+            }
+            Instruction::ORA2 | Instruction::ORA2k | Instruction::ORA2r | Instruction::ORA2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = a | b;
+                wst.write_short(c);
+            }	    
+            Instruction::EOR | Instruction::EORk | Instruction::EORr | Instruction::EORkr => {
+                let b = wst.read();
+                let a = wst.read();
+                let c = a ^ b;
+                wst.write(c);
+            }
+            Instruction::EOR2 | Instruction::EOR2k | Instruction::EOR2r | Instruction::EOR2kr => {
+                let b = wst.read_short();
+                let a = wst.read_short();
+                let c = a ^ b;
+                wst.write_short(c);
+            }	    
+            Instruction::SFT | Instruction::SFTk | Instruction::SFTr | Instruction::SFTkr => {
+                let shift = wst.read();
+                let a = wst.read();
+                let left = shift / 16;
+                let right = shift % 16;
+                // shifting a byte by 8 or more bits leaves nothing behind
+                let c = a
+                    .checked_shr(right as u32)
+                    .and_then(|a| a.checked_shl(left as u32))
+                    .unwrap_or(0);
+                wst.write(c);
+            }
+            Instruction::SFT2 | Instruction::SFT2k | Instruction::SFT2r | Instruction::SFT2kr => {
+                let shift = wst.read();
+                let a = wst.read_short();
+                let left = shift / 16;
+                let right = shift % 16;
+                let c = (a >> right) << left;
+                wst.write_short(c);
+            }
+            Instruction::DEI2 | Instruction::DEI2k | Instruction::DEI2r | Instruction::DEI2kr => {
+                let device = wst.read();
+                if !wst.faulted() {
+                    let val = devices.read_short(device, &mut cpu(mem, wst, rst, return_mode, pc));
+                    wst.write_short(val);
+                }
+            }
+            Instruction::DEO2 | Instruction::DEO2k | Instruction::DEO2r | Instruction::DEO2kr => {
+                let device = wst.read();
+                let val = wst.read_short();
+                if !wst.faulted() {
+                    devices.write_short(val, device, &mut cpu(mem, wst, rst, return_mode, pc));
+                }
+            }
+        }
+
+        let fault = match (real_wst.take_fault(), real_rst.take_fault()) {
+            (Some(fault), _) => Some((fault, false)),
+            (None, Some(fault)) => Some((fault, true)),
+            (None, None) => None,
+        };
+        if let Some((fault, return_stack)) = fault {
+            return Err(StackError {
+                fault,
+                return_stack,
+                opcode,
+                pc: opcode_pc,
+            });
+        }
+        self.pc = pc;
+        Ok(true)
+    }
+
+    /// Runs from `addr` until a BRK is reached.
+    ///
+    /// A fault jumps to the System vector when one is set, with the address
+    /// and opcode of the faulting instruction and the error code on the
+    /// working stack. It is returned when there is no handler, or when the
+    /// handler faults too.
+    ///
+    /// Console output is flushed when the vector ends, either way.
+    pub fn run_vector(&mut self, addr: u16) -> Result<(), StackError> {
+        let result = self.eval(addr);
+        self.devices.console.flush();
+        result
+    }
+
+    fn eval(&mut self, addr: u16) -> Result<(), StackError> {
+        self.pc = addr;
+        let mut handling = false;
+        loop {
+            match self.step() {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(error) => {
+                    let handler = self.devices.get_system_vector();
+                    if handler == 0 || handling {
+                        return Err(error);
+                    }
+                    handling = true;
+                    self.wst.p = 0;
+                    self.wst.set_current_opcode(0);
+                    self.wst.write_short(error.pc);
+                    self.wst.write(error.opcode);
+                    self.wst.write(error.fault.code());
+                    self.pc = handler;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn execute_test(code: Vec<u8>) -> MachineState {
+    let mut state = MachineState::from_code(code);
+    state.run_vector(0x0100).unwrap();
+    state
+}
+
+#[test]
+fn lit() {
+    let code = vec![0x80, 0x05];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x05;
+    let mut memory = vec![0; 65536];
+    memory[0x0100] = 0x80;
+    memory[0x0101] = 0x05;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(memory[..], state.mem[..]);
+}
+
+#[test]
+fn lit2() {
+    let code = vec![0x80, 0x05];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x05;
+    let mut memory = vec![0; 65536];
+    memory[0x0100] = 0x80;
+    memory[0x0101] = 0x05;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(memory[..], state.mem[..]);
+}
+
+#[test]
+fn inc() {
+    let code = vec![0x80, 0x05, 0x01];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x06;
+    let mut memory = vec![0; 65536];
+    memory[0x0100] = 0x80;
+    memory[0x0101] = 0x05;
+    memory[0x0102] = 0x01;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(memory[..], state.mem[..]);
+}
+
+#[test]
+fn inc_keep() {
+    let code = vec![0x80, 0x05, 0x81];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x05;
+    wst[1] = 0x06;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+}
+
+#[test]
+fn inc_return() {
+    let code = vec![0xc0, 0x05, 0x41];
+    let wst = vec![0; 256];
+    let mut rst = vec![0; 256];
+    rst[0] = 0x06;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(1, state.rst.p);
+}
+
+#[test]
+fn inc_keep_return() {
+    let code = vec![0xc0, 0x05, 0xc1];
+    let wst = vec![0; 256];
+    let mut rst = vec![0; 256];
+    rst[0] = 0x05;
+    rst[1] = 0x06;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(2, state.rst.p);
+}
+
+#[test]
+fn pop() {
+    let code = vec![0xa0, 0x12, 0x34, 0x02];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn nip() {
+    let code = vec![0xa0, 0x12, 0x34, 0x03];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x34;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn swp() {
+    let code = vec![0xa0, 0x12, 0x34, 0x04];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x34;
+    wst[1] = 0x12;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+}
+
+#[test]
+fn add() {
+    let code = vec![0xa0, 0x12, 0x34, 0x18];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12 + 0x34;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn sub() {
+    let code = vec![0xa0, 0x34, 0x12, 0x19];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x34 - 0x12;
+    wst[1] = 0x12;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn add2() {
+    let code = vec![0xa0, 0x00, 0x04, 0xa0, 0x00, 0x08, 0x38];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x0c;
+    wst[2] = 0x00;
+    wst[3] = 0x08;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+}
+
+#[test]
+fn rot() {
+    let code = vec![0xa0, 0x12, 0x34, 0x80, 0x56, 0x05];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x34;
+    wst[1] = 0x56;
+    wst[2] = 0x12;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+}
+
+#[test]
+fn dup() {
+    let code = vec![0xa0, 0x12, 0x34, 0x06];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    wst[2] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+}
+
+#[test]
+fn ovr() {
+    let code = vec![0xa0, 0x12, 0x34, 0x07];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    wst[2] = 0x12;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+}
+
+#[test]
+fn equ() {
+    let code = vec![0xa0, 0x12, 0x12, 0x08];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x01;
+    wst[1] = 0x12;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn equ_() {
+    let code = vec![0xa0, 0x12, 0x13, 0x08];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x13;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn neq() {
+    let code = vec![0xa0, 0x12, 0x34, 0x09];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x01;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn neq_() {
+    let code = vec![0xa0, 0x12, 0x12, 0x09];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x12;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn gth() {
+    let code = vec![0xa0, 0x12, 0x34, 0x0a];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn gth_() {
+    let code = vec![0xa0, 0x34, 0x12, 0x0a];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x01;
+    wst[1] = 0x12;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn lth() {
+    let code = vec![0xa0, 0x01, 0x01, 0x0b];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x01;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn lth_() {
+    let code = vec![0xa0, 0x00, 0x01, 0x0b];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x01;
+    wst[1] = 0x01;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn jmp() {
+    let code = vec![0xa0, 0x55, 0x34, 0x0c];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x55;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0x0100 + 0x04 + 0x34, state.pc);
+}
+
+#[test]
+fn jcn() {
+    let code = vec![0xa0, 0x01, 0x34, 0x0d];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x01;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(0x0100 + 0x04 + 0x34, state.pc);
+}
+
+#[test]
+fn jsr() {
+    let code = vec![0xa0, 0x12, 0x34, 0x0e];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    let mut rst = vec![0; 256];
+    rst[0] = 0x01;
+    rst[1] = 0x04;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0x0100 + 0x04 + 0x34, state.pc);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(2, state.rst.p);
+}
+
+#[test]
+fn jsr_and_jmp2r() {
+    let code = vec![0x80, 0x03, 0x0e, 0x80, 0xaa, 0x00, 0x80, 0x55, 0x6c];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x55;
+    wst[1] = 0xaa;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+    assert_eq!(0, state.rst.p);
+    assert_eq!(0x0105, state.pc);
+}
+
+#[test]
+fn jmp_backwards() {
+    let code = vec![0x80, 0x00, 0x01, 0x06, 0x80, 0x03, 0x0b, 0x80, 0xf8, 0x0d];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x03;
+    wst[1] = 0x00;
+    wst[2] = 0xf8;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0x010a, state.pc);
+}
+
+#[test]
+fn pc_wraps() {
+    let mut state = MachineState::from_code(vec![]);
+    state.mem[0xfffe] = 0xa0;
+    state.mem[0xffff] = 0x12;
+    state.mem[0x0000] = 0x34;
+    state.mem[0x0001] = 0x00;
+    state.run_vector(0xfffe).unwrap();
+    assert_eq!(0x12, state.wst.st[0]);
+    assert_eq!(0x34, state.wst.st[1]);
+    assert_eq!(0x0001, state.pc);
+
+    let code = vec![0xa0, 0xab, 0xcd, 0xa0, 0xff, 0xff, 0x35, 0xa0, 0xff, 0xff, 0x34];
+    let state = execute_test(code);
+    assert_eq!(0xab, state.mem[0xffff]);
+    assert_eq!(0xcd, state.mem[0x0000]);
+    assert_eq!(0xab, state.wst.st[0]);
+    assert_eq!(0xcd, state.wst.st[1]);
+    assert_eq!(2, state.wst.p);
+}
+
+#[test]
+fn sth() {
+    let code = vec![0xa0, 0x12, 0x34, 0x0f];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    let mut rst = vec![0; 256];
+    rst[0] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+}
+
+#[test]
+fn jmp2() {
+    let code = vec![0xa0, 0x01, 0x08, 0x2c];
+    let state = execute_test(code);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(0x0108, state.pc);
+}
+
+#[test]
+fn jcn2() {
+    let code = vec![0x80, 0x01, 0xa0, 0x01, 0x08, 0x2d];
+    let state = execute_test(code);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(0x0108, state.pc);
+
+    let code = vec![0x80, 0x00, 0xa0, 0x01, 0x08, 0x2d];
+    let state = execute_test(code);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(0x0106, state.pc);
+}
+
+#[test]
+fn jsr2_and_jmp2r() {
+    let code = vec![
+        0xa0, 0x01, 0x08, 0x2e, 0x80, 0xaa, 0x00, 0x00, 0x80, 0x55, 0x6c,
+    ];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x55;
+    wst[1] = 0xaa;
+    let mut rst = vec![0; 256];
+    rst[0] = 0x01;
+    rst[1] = 0x04;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(0, state.rst.p);
+    assert_eq!(0x0106, state.pc);
+}
+
+#[test]
+fn sth2() {
+    let code = vec![0xa0, 0x12, 0x34, 0x2f];
+    let mut rst = vec![0; 256];
+    rst[0] = 0x12;
+    rst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(2, state.rst.p);
+
+    let code = vec![0xe0, 0x12, 0x34, 0x6f];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+    assert_eq!(0, state.rst.p);
+}
+
+#[test]
+fn ldz_and_stz() {
+    let code = vec![0xa0, 0x50, 0x00, 0x11, 0x80, 0x00, 0x10];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x50;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+}
+
+#[test]
+fn ldz2_and_stz2() {
+    let code = vec![0xa0, 0x12, 0x34, 0x80, 0x50, 0x31, 0x80, 0x50, 0x30];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    wst[2] = 0x50;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+    assert_eq!(0x12, state.mem[0x50]);
+    assert_eq!(0x34, state.mem[0x51]);
+}
+
+#[test]
+fn ldr_and_str() {
+    let code = vec![0x80, 0x55, 0x80, 0x04, 0x13, 0x80, 0x01, 0x12, 0x00];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x55;
+    wst[1] = 0x04;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0x55, state.mem[0x0109]);
+}
+
+#[test]
+fn ldr_backwards() {
+    let code = vec![0x80, 0xfd, 0x12];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x80;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn ldr2_and_str2() {
+    let code = vec![0xa0, 0xab, 0xcd, 0x80, 0x04, 0x33, 0x80, 0x01, 0x32, 0x00];
+    let mut wst = vec![0; 256];
+    wst[0] = 0xab;
+    wst[1] = 0xcd;
+    wst[2] = 0x04;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+    assert_eq!(0xab, state.mem[0x010a]);
+    assert_eq!(0xcd, state.mem[0x010b]);
+}
+
+#[test]
+fn lda_and_sta() {
+    let code = vec![0x80, 0x66, 0xa0, 0x02, 0x00, 0x15, 0xa0, 0x02, 0x00, 0x14];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x66;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0x66, state.mem[0x0200]);
+}
+
+#[test]
+fn sta2_keep() {
+    let code = vec![0xa0, 0xab, 0xcd, 0xa0, 0x02, 0x00, 0xb5];
+    let mut wst = vec![0; 256];
+    wst[0] = 0xab;
+    wst[1] = 0xcd;
+    wst[2] = 0x02;
+    wst[3] = 0x00;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(4, state.wst.p);
+    assert_eq!(0xab, state.mem[0x0200]);
+    assert_eq!(0xcd, state.mem[0x0201]);
+}
+
+#[test]
+fn lda2_return() {
+    let code = vec![0xe0, 0x01, 0x00, 0x74];
+    let wst = vec![0; 256];
+    let mut rst = vec![0; 256];
+    rst[0] = 0xe0;
+    rst[1] = 0x01;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(0, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(2, state.rst.p);
+}
+
+#[test]
+fn mul_keep() {
+    let code = vec![0xa0, 0x50, 0x02, 0x9a];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x50;
+    wst[1] = 0x02;
+    wst[2] = 0xa0;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+}
+
+#[test]
+fn all_mul() {
+    let code_mul = vec![0xa0, 0x02, 0x02, 0x1a];
+    let code_mul2 = vec![0xa0, 0x00, 0x02, 0xa0, 0x00, 0x02, 0x3a];
+    let code_mulr = vec![0xe0, 0x02, 0x02, 0x5a];
+    let code_mul2r = vec![0xe0, 0x00, 0x02, 0xe0, 0x00, 0x02, 0x7a];
+    let code_mulk = vec![0xa0, 0x02, 0x02, 0x9a];
+    let code_mul2k = vec![0xa0, 0x00, 0x02, 0xa0, 0x00, 0x02, 0xba];
+    let code_mulkr = vec![0xe0, 0x02, 0x02, 0xda];
+    let code_mul2kr = vec![0xe0, 0x00, 0x02, 0xe0, 0x00, 0x02, 0xfa];
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x04;
+    wst[1] = 0x02;
+    let state = execute_test(code_mul);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x04;
+    wst[2] = 0x00;
+    wst[3] = 0x02;
+    let state = execute_test(code_mul2);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x04;
+    rst[1] = 0x02;
+    let state = execute_test(code_mulr);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(1, state.rst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x00;
+    rst[1] = 0x04;
+    rst[2] = 0x00;
+    rst[3] = 0x02;
+    let state = execute_test(code_mul2r);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(2, state.rst.p);
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x02;
+    wst[1] = 0x02;
+    wst[2] = 0x04;
+    let state = execute_test(code_mulk);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x02;
+    wst[2] = 0x00;
+    wst[3] = 0x02;
+    wst[4] = 0x00;
+    wst[5] = 0x04;
+    let state = execute_test(code_mul2k);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(6, state.wst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x02;
+    rst[1] = 0x02;
+    rst[2] = 0x04;
+    let state = execute_test(code_mulkr);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(3, state.rst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x00;
+    rst[1] = 0x02;
+    rst[2] = 0x00;
+    rst[3] = 0x02;
+    rst[4] = 0x00;
+    rst[5] = 0x04;
+    let state = execute_test(code_mul2kr);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(6, state.rst.p);
+}
+
+#[test]
+fn all_div() {
+    let code_div = vec![0xa0, 0x02, 0x02, 0x1b];
+    let code_div2 = vec![0xa0, 0x00, 0x02, 0xa0, 0x00, 0x02, 0x3b];
+    let code_divr = vec![0xe0, 0x02, 0x02, 0x5b];
+    let code_div2r = vec![0xe0, 0x00, 0x02, 0xe0, 0x00, 0x02, 0x7b];
+    let code_divk = vec![0xa0, 0x02, 0x02, 0x9b];
+    let code_div2k = vec![0xa0, 0x00, 0x02, 0xa0, 0x00, 0x02, 0xbb];
+    let code_divkr = vec![0xe0, 0x02, 0x02, 0xdb];
+    let code_div2kr = vec![0xe0, 0x00, 0x02, 0xe0, 0x00, 0x02, 0xfb];
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x01;
+    wst[1] = 0x02;
+    let state = execute_test(code_div);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x01;
+    wst[2] = 0x00;
+    wst[3] = 0x02;
+    let state = execute_test(code_div2);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x01;
+    rst[1] = 0x02;
+    let state = execute_test(code_divr);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(1, state.rst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x00;
+    rst[1] = 0x01;
+    rst[2] = 0x00;
+    rst[3] = 0x02;
+    let state = execute_test(code_div2r);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(2, state.rst.p);
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x02;
+    wst[1] = 0x02;
+    wst[2] = 0x01;
+    let state = execute_test(code_divk);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x02;
+    wst[2] = 0x00;
+    wst[3] = 0x02;
+    wst[4] = 0x00;
+    wst[5] = 0x01;
+    let state = execute_test(code_div2k);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(6, state.wst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x02;
+    rst[1] = 0x02;
+    rst[2] = 0x01;
+    let state = execute_test(code_divkr);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(3, state.rst.p);
+
+    let mut rst = vec![0; 256];
+    rst[0] = 0x00;
+    rst[1] = 0x02;
+    rst[2] = 0x00;
+    rst[3] = 0x02;
+    rst[4] = 0x00;
+    rst[5] = 0x01;
+    let state = execute_test(code_div2kr);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(6, state.rst.p);
+}
+
+#[test]
+fn and() {
+    let code = vec![0xa0, 0xf0, 0x0f, 0x1c];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x0f;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn ora_keep() {
+    let code = vec![0xa0, 0xf0, 0xff, 0x9d];
+    let mut wst = vec![0; 256];
+    wst[0] = 0xf0;
+    wst[1] = 0xff;
+    wst[2] = 0xff;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+}
+
+#[test]
+fn eor_keep() {
+    let code = vec![0xa0, 0xf0, 0xff, 0x9e];
+    let mut wst = vec![0; 256];
+    wst[0] = 0xf0;
+    wst[1] = 0xff;
+    wst[2] = 0x0f;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+}
+
+#[test]
+fn sft() {
+    let code = vec![0xa0, 0x34, 0x10, 0x1f];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x68;
+    wst[1] = 0x10;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+
+    let code = vec![0xa0, 0x34, 0x01, 0x1f];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x1a;
+    wst[1] = 0x01;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+
+    let code = vec![0xa0, 0x34, 0x33, 0x9f];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x34;
+    wst[1] = 0x33;
+    wst[2] = 0x30;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+
+    let code = vec![0xa0, 0x12, 0x48, 0x80, 0x34, 0xbf];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x48;
+    wst[2] = 0x34;
+    wst[3] = 0x09;
+    wst[4] = 0x20;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(5, state.wst.p);
+}
+
+// https://wiki.xxiivv.com/site/uxntal_opcodes.html
+#[cfg(test)]
+const OPCODE_TABLE: [&str; 256] = [
+    "BRK", "INC", "POP", "NIP", "SWP", "ROT", "DUP", "OVR",
+    "EQU", "NEQ", "GTH", "LTH", "JMP", "JCN", "JSR", "STH",
+    "LDZ", "STZ", "LDR", "STR", "LDA", "STA", "DEI", "DEO",
+    "ADD", "SUB", "MUL", "DIV", "AND", "ORA", "EOR", "SFT",
+    "JCI", "INC2", "POP2", "NIP2", "SWP2", "ROT2", "DUP2", "OVR2",
+    "EQU2", "NEQ2", "GTH2", "LTH2", "JMP2", "JCN2", "JSR2", "STH2",
+    "LDZ2", "STZ2", "LDR2", "STR2", "LDA2", "STA2", "DEI2", "DEO2",
+    "ADD2", "SUB2", "MUL2", "DIV2", "AND2", "ORA2", "EOR2", "SFT2",
+    "JMI", "INCr", "POPr", "NIPr", "SWPr", "ROTr", "DUPr", "OVRr",
+    "EQUr", "NEQr", "GTHr", "LTHr", "JMPr", "JCNr", "JSRr", "STHr",
+    "LDZr", "STZr", "LDRr", "STRr", "LDAr", "STAr", "DEIr", "DEOr",
+    "ADDr", "SUBr", "MULr", "DIVr", "ANDr", "ORAr", "EORr", "SFTr",
+    "JSI", "INC2r", "POP2r", "NIP2r", "SWP2r", "ROT2r", "DUP2r", "OVR2r",
+    "EQU2r", "NEQ2r", "GTH2r", "LTH2r", "JMP2r", "JCN2r", "JSR2r", "STH2r",
+    "LDZ2r", "STZ2r", "LDR2r", "STR2r", "LDA2r", "STA2r", "DEI2r", "DEO2r",
+    "ADD2r", "SUB2r", "MUL2r", "DIV2r", "AND2r", "ORA2r", "EOR2r", "SFT2r",
+    "LIT", "INCk", "POPk", "NIPk", "SWPk", "ROTk", "DUPk", "OVRk",
+    "EQUk", "NEQk", "GTHk", "LTHk", "JMPk", "JCNk", "JSRk", "STHk",
+    "LDZk", "STZk", "LDRk", "STRk", "LDAk", "STAk", "DEIk", "DEOk",
+    "ADDk", "SUBk", "MULk", "DIVk", "ANDk", "ORAk", "EORk", "SFTk",
+    "LIT2", "INC2k", "POP2k", "NIP2k", "SWP2k", "ROT2k", "DUP2k", "OVR2k",
+    "EQU2k", "NEQ2k", "GTH2k", "LTH2k", "JMP2k", "JCN2k", "JSR2k", "STH2k",
+    "LDZ2k", "STZ2k", "LDR2k", "STR2k", "LDA2k", "STA2k", "DEI2k", "DEO2k",
+    "ADD2k", "SUB2k", "MUL2k", "DIV2k", "AND2k", "ORA2k", "EOR2k", "SFT2k",
+    "LITr", "INCkr", "POPkr", "NIPkr", "SWPkr", "ROTkr", "DUPkr", "OVRkr",
+    "EQUkr", "NEQkr", "GTHkr", "LTHkr", "JMPkr", "JCNkr", "JSRkr", "STHkr",
+    "LDZkr", "STZkr", "LDRkr", "STRkr", "LDAkr", "STAkr", "DEIkr", "DEOkr",
+    "ADDkr", "SUBkr", "MULkr", "DIVkr", "ANDkr", "ORAkr", "EORkr", "SFTkr",
+    "LIT2r", "INC2kr", "POP2kr", "NIP2kr", "SWP2kr", "ROT2kr", "DUP2kr", "OVR2kr",
+    "EQU2kr", "NEQ2kr", "GTH2kr", "LTH2kr", "JMP2kr", "JCN2kr", "JSR2kr", "STH2kr",
+    "LDZ2kr", "STZ2kr", "LDR2kr", "STR2kr", "LDA2kr", "STA2kr", "DEI2kr", "DEO2kr",
+    "ADD2kr", "SUB2kr", "MUL2kr", "DIV2kr", "AND2kr", "ORA2kr", "EOR2kr", "SFT2kr",
+];
+
+#[test]
+fn decode_all_opcodes() {
+    for opcode in 0..=255u8 {
+        let instruction = Instruction::from(opcode);
+        assert_eq!(opcode, instruction as u8);
+        assert_eq!(OPCODE_TABLE[opcode as usize], format!("{:?}", instruction));
+    }
+}
+
+#[test]
+fn decode_mode_bits() {
+    for opcode in 0..=255u8 {
+        let base = opcode & 0x1f;
+        if base == 0x00 {
+            continue;
+        }
+        let mut name = OPCODE_TABLE[base as usize].to_string();
+        if opcode & 0x20 != 0 {
+            name.push('2');
+        }
+        if opcode & 0x80 != 0 {
+            name.push('k');
+        }
+        if opcode & 0x40 != 0 {
+            name.push('r');
+        }
+        assert_eq!(name, format!("{:?}", Instruction::from(opcode)));
+        assert_eq!(opcode & 0x40 != 0, Instruction::from(opcode).is_return());
+    }
+}
+
+#[test]
+fn jci() {
+    let code = vec![0x80, 0x01, 0x20, 0x00, 0x02, 0x80, 0xaa, 0x80, 0xbb];
+    let mut wst = vec![0; 256];
+    wst[0] = 0xbb;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0x0109, state.pc);
+
+    let code = vec![0x80, 0x00, 0x20, 0x00, 0x02, 0x80, 0xaa];
+    let mut wst = vec![0; 256];
+    wst[0] = 0xaa;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0x0107, state.pc);
+}
+
+#[test]
+fn jmi() {
+    let code = vec![0x40, 0x00, 0x02, 0x80, 0xaa, 0x80, 0xbb];
+    let mut wst = vec![0; 256];
+    wst[0] = 0xbb;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+    assert_eq!(0, state.rst.p);
+    assert_eq!(0x0107, state.pc);
+}
+
+#[test]
+fn jsi_and_jmp2r() {
+    let code = vec![0x60, 0x00, 0x03, 0x80, 0xaa, 0x00, 0x80, 0x55, 0x6c];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x55;
+    wst[1] = 0xaa;
+    let mut rst = vec![0; 256];
+    rst[0] = 0x01;
+    rst[1] = 0x03;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(0, state.rst.p);
+    assert_eq!(0x0105, state.pc);
+}
+
+#[test]
+fn legacy_lit_column() {
+    let code = vec![0x20, 0x12, 0x34, 0x40, 0x56, 0x60, 0x78, 0x9a];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x12;
+    wst[1] = 0x34;
+    let mut rst = vec![0; 256];
+    rst[0] = 0x56;
+    rst[1] = 0x78;
+    rst[2] = 0x9a;
+    let mut state = MachineState::from_code(code);
+    state.semantics = Semantics::Legacy;
+    state.run_vector(0x0100).unwrap();
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(2, state.wst.p);
+    assert_eq!(rst, state.rst.st);
+    assert_eq!(3, state.rst.p);
+}
+
+#[test]
+fn inc_wraps() {
+    let code = vec![0x80, 0xff, 0x01, 0xa0, 0xff, 0xff, 0x21];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    wst[1] = 0x00;
+    wst[2] = 0x00;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(3, state.wst.p);
+}
+
+#[test]
+fn arithmetic_wraps() {
+    let code = vec![0xa0, 0xf0, 0x20, 0x18];
+    let state = execute_test(code);
+    assert_eq!(0x10, state.wst.st[0]);
+
+    let code = vec![0xa0, 0x10, 0x20, 0x19];
+    let state = execute_test(code);
+    assert_eq!(0xf0, state.wst.st[0]);
+
+    let code = vec![0xa0, 0x10, 0x20, 0x1a];
+    let state = execute_test(code);
+    assert_eq!(0x00, state.wst.st[0]);
+
+    let code = vec![0xa0, 0x00, 0x01, 0xa0, 0x00, 0x02, 0x39];
+    let state = execute_test(code);
+    assert_eq!(0xff, state.wst.st[0]);
+    assert_eq!(0xff, state.wst.st[1]);
+}
+
+#[test]
+fn div_by_zero() {
+    let code = vec![0xa0, 0x12, 0x00, 0x1b];
+    let mut wst = vec![0; 256];
+    wst[0] = 0x00;
+    let state = execute_test(code);
+    assert_eq!(wst, state.wst.st);
+    assert_eq!(1, state.wst.p);
+
+    let code = vec![0xa0, 0x12, 0x34, 0xa0, 0x00, 0x00, 0x3b];
+    let state = execute_test(code);
+    assert_eq!(0x00, state.wst.st[0]);
+    assert_eq!(0x00, state.wst.st[1]);
+    assert_eq!(2, state.wst.p);
+}
+
+#[test]
+fn comparisons_short() {
+    let code = vec![0xa0, 0x12, 0x34, 0xa0, 0x12, 0x34, 0x28];
+    let state = execute_test(code);
+    assert_eq!(0x01, state.wst.st[0]);
+    assert_eq!(1, state.wst.p);
+
+    let code = vec![0xa0, 0x12, 0x34, 0xa0, 0x12, 0x34, 0x2a];
+    let state = execute_test(code);
+    assert_eq!(0x00, state.wst.st[0]);
+    assert_eq!(1, state.wst.p);
+}
+
+#[test]
+fn stack_wraps() {
+    let code = vec![0x02];
+    let state = execute_test(code);
+    assert_eq!(0xff, state.wst.p);
+
+    let code = (0..=255).flat_map(|i| [0x80, i]).collect();
+    let state = execute_test(code);
+    assert_eq!(0x00, state.wst.p);
+    assert_eq!(0xff, state.wst.st[0xff]);
+}
+
+#[cfg(test)]
+fn execute_test_strict(code: Vec<u8>) -> (MachineState, Result<(), StackError>) {
+    let mut state = MachineState::from_code(code);
+    state.set_strict(true);
+    let result = state.run_vector(0x0100);
+    (state, result)
+}
+
+#[test]
+fn strict_underflow() {
+    let code = vec![0x80, 0x01, 0x18];
+    let (state, result) = execute_test_strict(code);
+    let fault = StackError {
+        fault: StackFault::Underflow,
+        return_stack: false,
+        opcode: 0x18,
+        pc: 0x0102,
+    };
+    assert_eq!(Err(fault), result);
+    assert_eq!(0x0102, state.pc);
+    assert_eq!("Working-stack underflow at 0x0102 (ADD)", fault.to_string());
+
+    let code = vec![0x80, 0x01, 0x98];
+    let (state, result) = execute_test_strict(code);
+    assert_eq!(Err(StackFault::Underflow), result.map_err(|f| f.fault));
+    assert_eq!(0x0102, state.pc);
+
+    let code = vec![0x6c];
+    let (_, result) = execute_test_strict(code);
+    assert_eq!(
+        "Return-stack underflow at 0x0100 (JMP2r)",
+        result.unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn strict_overflow() {
+    let code = (0..=255).flat_map(|i| [0x80, i]).collect();
+    let (state, result) = execute_test_strict(code);
+    let fault = StackError {
+        fault: StackFault::Overflow,
+        return_stack: false,
+        opcode: 0x80,
+        pc: 0x02fe,
+    };
+    assert_eq!(Err(fault), result);
+    assert_eq!(0xff, state.wst.p);
+}
+
+#[test]
+fn strict_division_by_zero() {
+    // #01 #00 DIV
+    let code = vec![0x80, 0x01, 0x80, 0x00, 0x1b];
+    assert_eq!(execute_test(code.clone()).wst.st[0], 0x00);
+    let (_, result) = execute_test_strict(code);
+    assert_eq!(
+        "Working-stack division by zero at 0x0104 (DIV)",
+        result.unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn strict_fault_has_no_side_effects() {
+    // #2a #0200 STA: stores 0x2a at 0x0200
+    let (state, result) = execute_test_strict(vec![0x80, 0x2a, 0xa0, 0x02, 0x00, 0x15]);
+    assert_eq!(Ok(()), result);
+    assert_eq!(0x2a, state.mem[0x0200]);
+
+    // #0100 STA: underflows, without storing over its own code
+    let (state, result) = execute_test_strict(vec![0xa0, 0x01, 0x00, 0x15]);
+    assert_eq!(Err(StackFault::Underflow), result.map_err(|f| f.fault));
+    assert_eq!(0x0103, state.pc);
+    assert_eq!(0xa0, state.mem[0x0100]);
+
+    // #12 DEO underflows too
+    let (state, _) = execute_test_strict(vec![0x80, 0x12, 0x17]);
+    assert!(state.devices.page.iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn system_vector_handles_faults() {
+    // |0100 ;on-error .System/vector DEO2 #01 ADD2 #02 BRK
+    // @on-error #ff
+    let mut code = vec![0xa0, 0x01, 0x0a, 0x80, 0x00, 0x37, 0x80, 0x01, 0x38, 0x00];
+    code.extend([0x80, 0xff, 0x00]);
+    let (state, result) = execute_test_strict(code);
+    assert_eq!(Ok(()), result);
+    assert_eq!(state.wst.p, 5);
+    assert_eq!(state.wst.st[0..5], [0x01, 0x08, 0x38, 0x01, 0xff]);
+    assert_eq!(0x010c, state.pc);
+}
+
+#[test]
+fn system_vector_faulting_deo() {
+    // |0100 ;on-error .System/vector DEO2 DEO BRK @on-error #ff
+    // A faulting DEO must not write to System/vector before it is read.
+    for opcode in [0x17, 0x37] {
+        let code = vec![0xa0, 0x01, 0x08, 0x80, 0x00, 0x37, opcode, 0x00, 0x80, 0xff, 0x00];
+        let (state, result) = execute_test_strict(code);
+        assert_eq!(Ok(()), result);
+        assert_eq!(state.wst.st[0..5], [0x01, 0x06, opcode, 0x01, 0xff]);
+        assert_eq!(0x0108, state.devices.get_system_vector());
+    }
+}
+
+#[test]
+fn system_vector_faulting_handler() {
+    // |0100 ;on-error .System/vector DEO2 ADD BRK @on-error POP2 POP2 POP
+    let code = vec![
+        0xa0, 0x01, 0x08, 0x80, 0x00, 0x37, 0x18, 0x00, 0x22, 0x22, 0x02,
+    ];
+    let (_, result) = execute_test_strict(code);
+    assert_eq!(
+        "Working-stack underflow at 0x010a (POP)",
+        result.unwrap_err().to_string()
+    );
+}
+
+#[cfg(test)]
+use proptest::prelude::*;
+
+// Reference ALU following uxn.c: compute on wide integers and truncate the
+// result to the width of the operation.
+#[cfg(test)]
+fn reference_alu(opcode: u8, a: u32, b: u32) -> u32 {
+    let mask = if opcode & 0x20 != 0 { 0xffff } else { 0xff };
+    match opcode & 0x1f {
+        0x01 => (a + 1) & mask,
+        0x08 => (a == b) as u32,
+        0x09 => (a != b) as u32,
+        0x0a => (a > b) as u32,
+        0x0b => (a < b) as u32,
+        0x18 => (a + b) & mask,
+        0x19 => a.wrapping_sub(b) & mask,
+        0x1a => (a * b) & mask,
+        0x1b => a.checked_div(b).unwrap_or(0),
+        0x1c => a & b,
+        0x1d => a | b,
+        0x1e => a ^ b,
+        0x1f => ((a >> (b & 0x0f)) << ((b & 0xf0) >> 4)) & mask,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+fn execute_alu(opcode: u8, a: u16, b: u16) -> u32 {
+    let short = opcode & 0x20 != 0;
+    let base = opcode & 0x1f;
+    let mut code = if short {
+        vec![0xa0, (a >> 8) as u8, a as u8]
+    } else {
+        vec![0x80, a as u8]
+    };
+    if base == 0x1f || (base != 0x01 && !short) {
+        code.extend([0x80, b as u8]);
+    } else if base != 0x01 {
+        code.extend([0xa0, (b >> 8) as u8, b as u8]);
+    }
+    code.push(opcode);
+    let state = execute_test(code);
+    let p = state.wst.p as usize;
+    if short && !(0x08..=0x0b).contains(&base) {
+        (state.wst.st[p - 2] as u32) << 8 | state.wst.st[p - 1] as u32
+    } else {
+        state.wst.st[p - 1] as u32
+    }
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn alu_matches_reference(
+        base in prop::sample::select(vec![
+            0x01u8, 0x08, 0x09, 0x0a, 0x0b, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e, 0x1f,
+        ]),
+        short: bool,
+        a: u16,
+        b: u16,
+    ) {
+        let opcode = if short { base | 0x20 } else { base };
+        let a = if short { a } else { a & 0xff };
+        let b = if short && base != 0x1f { b } else { b & 0xff };
+        prop_assert_eq!(reference_alu(opcode, a as u32, b as u32), execute_alu(opcode, a, b));
+    }
+}
+
+#[test]
+fn unmapped_ports_read_back() {
+    // #2a #72 DEO #72 DEI
+    let code = vec![0x80, 0x2a, 0x80, 0x72, 0x17, 0x80, 0x72, 0x16];
+    let state = execute_test(code);
+    assert_eq!(state.wst.p, 1);
+    assert_eq!(state.wst.st[0], 0x2a);
+}
+
+#[test]
+fn device_page_read_back() {
+    // #0123 .Screen/x DEO2 #abcd .Screen/addr DEO2 .Screen/x DEI2 .Screen/addr DEI2
+    let code = vec![
+        0xa0, 0x01, 0x23, 0x80, 0x28, 0x37, 0xa0, 0xab, 0xcd, 0x80, 0x2c, 0x37, 0x80, 0x28, 0x36,
+        0x80, 0x2c, 0x36,
+    ];
+    let state = execute_test(code);
+    assert_eq!(state.wst.p, 4);
+    assert_eq!(state.wst.st[0..4], [0x01, 0x23, 0xab, 0xcd]);
+    assert_eq!(state.devices.page[0x28..0x2e], [0x01, 0x23, 0, 0, 0xab, 0xcd]);
+}
+
+#[test]
+fn system_state_halts() {
+    // #01 .System/state DEO #05
+    let code = vec![0x80, 0x01, 0x80, 0x0f, 0x17, 0x80, 0x05];
+    let mut state = execute_test(code);
+    assert_eq!(state.wst.p, 0);
+    assert_eq!(state.exit_code(), Some(1));
+    state.run_vector(0x0105).unwrap();
+    assert_eq!(state.wst.p, 0);
+}
+
+#[test]
+fn system_state_exit_code() {
+    // #85 .System/state DEO
+    let code = vec![0x80, 0x85, 0x80, 0x0f, 0x17];
+    let state = execute_test(code);
+    assert_eq!(state.exit_code(), Some(5));
+}
+
+#[test]
+fn system_expansion_port() {
+    // ;fill .System/expansion DEO2 BRK @fill 00 0002 0001 0000 77
+    let code = vec![
+        0xa0, 0x01, 0x07, 0x80, 0x02, 0x37, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x77,
+    ];
+    let state = execute_test(code);
+    assert_eq!(state.mem.get(0x10000), 0x77);
+    assert_eq!(state.mem.get(0x10001), 0x77);
+    assert_eq!(state.mem.get(0x10002), 0x00);
+}
+
+#[test]
+fn system_metadata_port() {
+    // ;meta .System/metadata DEO2 BRK @meta 00 "Hi 00
+    let code = vec![0xa0, 0x01, 0x07, 0x80, 0x06, 0x37, 0x00, 0x00, b'H', b'i', 0x00];
+    let state = execute_test(code);
+    assert_eq!(state.metadata().unwrap().name, "Hi");
+    assert!(execute_test(vec![0x00]).metadata().is_none());
+}
+
+#[test]
+fn exit_status() {
+    let state = execute_test(vec![0x00]);
+    assert_eq!(state.exit_status(Ok(())), None);
+    // #2a .System/state DEO
+    let state = execute_test(vec![0x80, 0x2a, 0x80, 0x0f, 0x17]);
+    assert_eq!(state.exit_status(Ok(())), Some(0x2a));
+    let (state, result) = execute_test_strict(vec![0x18]);
+    assert_eq!(state.exit_status(result), Some(1));
+}
+
+#[test]
+fn console_input_vector() {
+    // ;on-console .Console/vector DEO2 BRK
+    // @on-console .Console/read DEI .Console/type DEI BRK
+    let code = vec![
+        0xa0, 0x01, 0x07, 0x80, 0x10, 0x37, 0x00, 0x80, 0x12, 0x16, 0x80, 0x17, 0x16, 0x00,
+    ];
+    let mut state = execute_test(code);
+    state.console_input(b'a', ConsoleInput::Stdin).unwrap();
+    state.console_input(0x00, ConsoleInput::ArgumentEnd).unwrap();
+    assert_eq!(state.wst.p, 4);
+    assert_eq!(state.wst.st[0..4], [b'a', 0x01, 0x00, 0x04]);
+}
+
+#[test]
+fn boot_with_arguments() {
+    // .Console/type DEI ;on-console .Console/vector DEO2 BRK
+    // @on-console .Console/read DEI .Console/type DEI BRK
+    let code = vec![
+        0x80, 0x17, 0x16, 0xa0, 0x01, 0x0a, 0x80, 0x10, 0x37, 0x00, 0x80, 0x12, 0x16, 0x80, 0x17,
+        0x16, 0x00,
+    ];
+    let mut state = MachineState::from_code(code);
+    state.boot(&["ab".to_string(), "c".to_string()]).unwrap();
+    assert_eq!(state.wst.p, 11);
+    assert_eq!(
+        state.wst.st[0..11],
+        [0x02, b'a', 0x02, b'b', 0x02, b'\n', 0x03, b'c', 0x02, b'\n', 0x04]
+    );
+}
+
+#[test]
+fn console_output_is_byte_exact() {
+    // #ff .Console/write DEO #e9 .Console/error DEO BRK
+    let code = vec![0x80, 0xff, 0x80, 0x18, 0x17, 0x80, 0xe9, 0x80, 0x19, 0x17];
+    let mut state = MachineState::from_code(code);
+    while state.step().unwrap() {}
+    assert_eq!(state.devices.console.pending_stdout(), [0xff]);
+    assert_eq!(state.devices.console.pending_stderr(), [0xe9]);
+    assert_eq!(state.devices.console.take_pending(), (vec![0xff], vec![0xe9]));
+    assert!(state.devices.console.pending_stdout().is_empty());
+    assert!(state.devices.console.pending_stderr().is_empty());
+}
@@ -0,0 +1,66 @@
+/* screen_blit and the blending table from the reference Varvara screen.c,
+   drawing every sprite mode over prefilled 12x12 layers. Regenerate the
+   golden file with:
+
+   cc tests/golden/screen.c -o /tmp/screen && /tmp/screen > tests/golden/sprites.txt */
+#include <stdio.h>
+
+typedef unsigned char Uint8;
+typedef unsigned short Uint16;
+
+#define WIDTH 12
+#define HEIGHT 12
+
+static Uint8 blending[4][16] = {
+	{0, 0, 0, 0, 1, 0, 1, 1, 2, 2, 0, 2, 3, 3, 3, 0},
+	{0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3},
+	{1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1},
+	{2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2}};
+
+static void
+screen_blit(Uint8 *dst, Uint8 *ram, Uint16 addr, int x1, int y1, int color, int flipx, int flipy, int twobpp)
+{
+	int v, h, width = WIDTH, height = HEIGHT, opaque = (color % 5) || !color;
+	for(v = 0; v < 8; v++) {
+		Uint16 c = ram[(addr + v) & 0xffff] | (twobpp ? (ram[(addr + v + 8) & 0xffff] << 8) : 0);
+		Uint16 y = y1 + (flipy ? 7 - v : v);
+		for(h = 7; h >= 0; --h, c >>= 1) {
+			Uint8 ch = (c & 1) | ((c >> 7) & 2);
+			if(opaque || ch) {
+				Uint16 x = x1 + (flipx ? 7 - h : h);
+				if(x < width && y < height)
+					dst[x + y * width] = blending[ch][color];
+			}
+		}
+	}
+}
+
+static Uint8 ram[0x10000];
+
+int
+main(void)
+{
+	Uint8 sprite[16] = {
+		0xf0, 0x80, 0xc0, 0xa0, 0x90, 0x88, 0x04, 0x03,
+		0x0f, 0x01, 0x33, 0x55, 0x00, 0xff, 0x10, 0x08};
+	int mode, i, x, y;
+	for(i = 0; i < 16; i++)
+		ram[0x0200 + i] = sprite[i];
+	for(mode = 0; mode < 0x100; mode++) {
+		Uint8 bg[WIDTH * HEIGHT], fg[WIDTH * HEIGHT];
+		for(y = 0; y < HEIGHT; y++)
+			for(x = 0; x < WIDTH; x++) {
+				bg[x + y * WIDTH] = (x + y) % 4;
+				fg[x + y * WIDTH] = (x * 3 + y) % 4;
+			}
+		screen_blit(mode & 0x40 ? fg : bg, ram, 0x0200, 2, 2, mode & 0xf, mode & 0x10, mode & 0x20, !!(mode & 0x80));
+		printf("%02x ", mode);
+		for(i = 0; i < WIDTH * HEIGHT; i++)
+			printf("%d", bg[i]);
+		printf(" ");
+		for(i = 0; i < WIDTH * HEIGHT; i++)
+			printf("%d", fg[i]);
+		printf("\n");
+	}
+	return 0;
+}